@@ -1,7 +1,7 @@
 #![cfg(target_os = "macos")]
 
 use criterion::{Criterion, criterion_group, criterion_main};
-use sysproxy::{Autoproxy, Sysproxy};
+use sysproxy::{Autoproxy, BypassList, BypassRule, Sysproxy};
 use system_configuration::core_foundation::string::CFString;
 
 fn get_valid_service() -> CFString {
@@ -51,7 +51,8 @@ fn bench_set_http(c: &mut Criterion) {
         enable: false,
         host: "127.0.0.1".into(),
         port: 8080,
-        bypass: "".into(),
+        bypass: Default::default(),
+        ..Default::default()
     };
     c.bench_function("set_http", |b| b.iter(|| proxy.set_http(&service)));
 }
@@ -62,7 +63,8 @@ fn bench_set_https(c: &mut Criterion) {
         enable: false,
         host: "127.0.0.1".into(),
         port: 8080,
-        bypass: "".into(),
+        bypass: Default::default(),
+        ..Default::default()
     };
     c.bench_function("set_https", |b| b.iter(|| proxy.set_https(&service)));
 }
@@ -73,7 +75,8 @@ fn bench_set_socks(c: &mut Criterion) {
         enable: false,
         host: "127.0.0.1".into(),
         port: 8080,
-        bypass: "".into(),
+        bypass: Default::default(),
+        ..Default::default()
     };
     c.bench_function("set_socks", |b| b.iter(|| proxy.set_socks(&service)));
 }
@@ -84,7 +87,11 @@ fn bench_set_bypass(c: &mut Criterion) {
         enable: false,
         host: "".into(),
         port: 0,
-        bypass: "127.0.0.1,localhost".into(),
+        bypass: BypassList::from(vec![
+            BypassRule::Host("127.0.0.1".into()),
+            BypassRule::Host("localhost".into()),
+        ]),
+        ..Default::default()
     };
     c.bench_function("set_bypass", |b| b.iter(|| proxy.set_bypass(&service)));
 }
@@ -94,7 +101,8 @@ fn bench_set_system_proxy(c: &mut Criterion) {
         enable: false,
         host: "127.0.0.1".into(),
         port: 8080,
-        bypass: "".into(),
+        bypass: Default::default(),
+        ..Default::default()
     };
     c.bench_function("set_system_proxy", |b| b.iter(|| proxy.set_system_proxy()));
 }
@@ -0,0 +1,334 @@
+//! Pluggable access to the proxy settings.
+//!
+//! The inherent methods such as [`Sysproxy::get_system_proxy`] always talk to the running
+//! system. Code that takes a [`ProxyBackend`] can be pointed at a specific desktop, or at a
+//! [`MockBackend`] in tests.
+
+use crate::{Autoproxy, Error, ProxyChange, ProxyDiff, Result, Sysproxy};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+#[cfg(target_os = "linux")]
+pub use crate::environment::EnvironmentBackend;
+#[cfg(target_os = "linux")]
+pub use crate::linux::{GnomeBackend, GnomeWriteSet, KdeBackend, KdeEnvProxy};
+#[cfg(target_os = "macos")]
+pub use crate::macos::MacosBackend;
+#[cfg(target_os = "windows")]
+pub use crate::windows::WindowsBackend;
+
+/// Get and set the manual and auto proxy of one settings store.
+pub trait ProxyBackend: Send + Sync {
+    fn get_system_proxy(&self) -> Result<Sysproxy>;
+
+    fn set_system_proxy(&self, proxy: &Sysproxy) -> Result<()>;
+
+    /// Bring the settings to `proxy`, where `diff` is the current proxy diffed against it.
+    ///
+    /// Backends that can write single fields only write the changed ones, the default writes
+    /// everything.
+    fn set_system_proxy_diff(&self, proxy: &Sysproxy, diff: &ProxyDiff) -> Result<()> {
+        let _ = diff;
+        self.set_system_proxy(proxy)
+    }
+
+    /// The diff from `current`, as read back, to `proxy` that
+    /// [`ProxyBackend::set_system_proxy_diff`] acts on.
+    ///
+    /// Fields the backend can't read back or doesn't write are left out, otherwise they would
+    /// differ on every comparison. The default compares everything.
+    fn system_proxy_diff(&self, current: &Sysproxy, proxy: &Sysproxy) -> ProxyDiff {
+        current.diff(proxy)
+    }
+
+    fn get_auto_proxy(&self) -> Result<Autoproxy>;
+
+    fn set_auto_proxy(&self, autoproxy: &Autoproxy) -> Result<()>;
+}
+
+/// The settings of the running system, the same as the inherent methods.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemBackend;
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+impl ProxyBackend for SystemBackend {
+    fn get_system_proxy(&self) -> Result<Sysproxy> {
+        Sysproxy::get_system_proxy()
+    }
+
+    fn set_system_proxy(&self, proxy: &Sysproxy) -> Result<()> {
+        proxy.set_system_proxy()
+    }
+
+    fn system_proxy_diff(&self, current: &Sysproxy, proxy: &Sysproxy) -> ProxyDiff {
+        #[cfg(target_os = "linux")]
+        let backend = GnomeBackend::default();
+        #[cfg(target_os = "macos")]
+        let backend = MacosBackend;
+        #[cfg(target_os = "windows")]
+        let backend = WindowsBackend;
+        backend.system_proxy_diff(current, proxy)
+    }
+
+    fn get_auto_proxy(&self) -> Result<Autoproxy> {
+        Autoproxy::get_auto_proxy()
+    }
+
+    fn set_auto_proxy(&self, autoproxy: &Autoproxy) -> Result<()> {
+        autoproxy.set_auto_proxy()
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+impl ProxyBackend for SystemBackend {
+    fn get_system_proxy(&self) -> Result<Sysproxy> {
+        Err(Error::NotSupport)
+    }
+
+    fn set_system_proxy(&self, _proxy: &Sysproxy) -> Result<()> {
+        Err(Error::NotSupport)
+    }
+
+    fn get_auto_proxy(&self) -> Result<Autoproxy> {
+        Err(Error::NotSupport)
+    }
+
+    fn set_auto_proxy(&self, _autoproxy: &Autoproxy) -> Result<()> {
+        Err(Error::NotSupport)
+    }
+}
+
+/// A [`MockBackend`] call, used to inject failures and count calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockOperation {
+    GetSystemProxy,
+    SetSystemProxy,
+    GetAutoProxy,
+    SetAutoProxy,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    sysproxy: Sysproxy,
+    autoproxy: Autoproxy,
+    drops_auth: bool,
+    fail_always: Vec<MockOperation>,
+    fail_next: Vec<MockOperation>,
+    calls: HashMap<MockOperation, usize>,
+}
+
+/// In-memory backend for tests.
+///
+/// Like the real backends, enabling the manual proxy turns off the auto proxy and the other
+/// way round, and invalid proxies are refused.
+///
+/// Example:
+/// ```
+/// use sysproxy::{MockBackend, MockOperation, ProxyBackend, Sysproxy};
+///
+/// let backend = MockBackend::new();
+/// let proxy = Sysproxy::builder().host("127.0.0.1").port(7890).build().unwrap();
+/// backend.set_system_proxy(&proxy).unwrap();
+/// assert_eq!(backend.get_system_proxy().unwrap(), proxy);
+///
+/// backend.fail_next(MockOperation::GetSystemProxy);
+/// assert!(backend.get_system_proxy().is_err());
+/// assert_eq!(backend.calls(MockOperation::GetSystemProxy), 2);
+/// ```
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    state: Mutex<MockState>,
+}
+
+impl MockBackend {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from the given settings.
+    #[inline]
+    pub fn with_state(sysproxy: Sysproxy, autoproxy: Autoproxy) -> Self {
+        Self {
+            state: Mutex::new(MockState {
+                sysproxy,
+                autoproxy,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// The stored manual proxy, without counting a call.
+    #[inline]
+    pub fn sysproxy(&self) -> Sysproxy {
+        self.lock().sysproxy.clone()
+    }
+
+    /// The stored auto proxy, without counting a call.
+    #[inline]
+    pub fn autoproxy(&self) -> Autoproxy {
+        self.lock().autoproxy.clone()
+    }
+
+    /// Change the stored manual proxy behind the caller's back, like another program would.
+    #[inline]
+    pub fn replace_sysproxy(&self, sysproxy: Sysproxy) {
+        self.lock().sysproxy = sysproxy;
+    }
+
+    /// Change the stored auto proxy behind the caller's back, like another program would.
+    #[inline]
+    pub fn replace_autoproxy(&self, autoproxy: Autoproxy) {
+        self.lock().autoproxy = autoproxy;
+    }
+
+    /// Store proxies without their credentials from now on, like the macOS and Windows
+    /// backends, which can't read them back.
+    #[inline]
+    pub fn drop_auth(&self) {
+        self.lock().drops_auth = true;
+    }
+
+    /// Fail every `operation` until [`MockBackend::clear_failures`].
+    #[inline]
+    pub fn fail_always(&self, operation: MockOperation) {
+        self.lock().fail_always.push(operation);
+    }
+
+    /// Fail the next `operation` only.
+    #[inline]
+    pub fn fail_next(&self, operation: MockOperation) {
+        self.lock().fail_next.push(operation);
+    }
+
+    #[inline]
+    pub fn clear_failures(&self) {
+        let mut state = self.lock();
+        state.fail_always.clear();
+        state.fail_next.clear();
+    }
+
+    /// How often `operation` was called, failed calls included.
+    #[inline]
+    pub fn calls(&self, operation: MockOperation) -> usize {
+        self.lock().calls.get(&operation).copied().unwrap_or(0)
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Count the call and lock the state, or fail if a failure was injected.
+    fn begin(&self, operation: MockOperation) -> Result<MutexGuard<'_, MockState>> {
+        let mut state = self.lock();
+        *state.calls.entry(operation).or_default() += 1;
+
+        let injected = if let Some(i) = state.fail_next.iter().position(|op| *op == operation) {
+            state.fail_next.remove(i);
+            true
+        } else {
+            state.fail_always.contains(&operation)
+        };
+        if injected {
+            return Err(Error::Io(io::Error::other(format!(
+                "injected failure: {operation:?}"
+            ))));
+        }
+        Ok(state)
+    }
+}
+
+impl ProxyBackend for MockBackend {
+    fn get_system_proxy(&self) -> Result<Sysproxy> {
+        Ok(self.begin(MockOperation::GetSystemProxy)?.sysproxy.clone())
+    }
+
+    fn set_system_proxy(&self, proxy: &Sysproxy) -> Result<()> {
+        let mut state = self.begin(MockOperation::SetSystemProxy)?;
+        proxy.validate()?;
+        if proxy.enable {
+            state.autoproxy.enable = false;
+        }
+        state.sysproxy = proxy.clone();
+        if state.drops_auth {
+            state.sysproxy.auth = None;
+        }
+        Ok(())
+    }
+
+    fn system_proxy_diff(&self, current: &Sysproxy, proxy: &Sysproxy) -> ProxyDiff {
+        let diff = current.diff(proxy);
+        match self.lock().drops_auth {
+            true => diff.retain(|change| *change != ProxyChange::Auth),
+            false => diff,
+        }
+    }
+
+    fn get_auto_proxy(&self) -> Result<Autoproxy> {
+        Ok(self.begin(MockOperation::GetAutoProxy)?.autoproxy.clone())
+    }
+
+    fn set_auto_proxy(&self, autoproxy: &Autoproxy) -> Result<()> {
+        let mut state = self.begin(MockOperation::SetAutoProxy)?;
+        if autoproxy.enable {
+            state.sysproxy.enable = false;
+        }
+        state.autoproxy = autoproxy.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    fn proxy() -> Sysproxy {
+        Sysproxy::builder()
+            .host("127.0.0.1")
+            .port(7890)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn mock_switches_between_manual_and_auto() {
+        let backend = MockBackend::new();
+        backend.set_system_proxy(&proxy()).unwrap();
+
+        let auto = Autoproxy {
+            url: "http://127.0.0.1/pac".into(),
+            enable: true,
+        };
+        backend.set_auto_proxy(&auto).unwrap();
+        assert!(!backend.get_system_proxy().unwrap().enable);
+        assert_eq!(backend.get_auto_proxy().unwrap(), auto);
+    }
+
+    #[test]
+    fn mock_refuses_invalid_proxy() {
+        let backend = MockBackend::new();
+        let invalid = Sysproxy {
+            enable: true,
+            ..Default::default()
+        };
+        assert!(backend.set_system_proxy(&invalid).is_err());
+        assert_eq!(backend.sysproxy(), Sysproxy::default());
+    }
+
+    #[test]
+    fn mock_injects_failures() {
+        let backend = MockBackend::new();
+        backend.fail_always(MockOperation::SetSystemProxy);
+        assert!(backend.set_system_proxy(&proxy()).is_err());
+        assert!(backend.set_system_proxy(&proxy()).is_err());
+        backend.clear_failures();
+        backend.set_system_proxy(&proxy()).unwrap();
+        assert_eq!(backend.calls(MockOperation::SetSystemProxy), 3);
+        assert_eq!(backend.sysproxy(), proxy());
+    }
+}
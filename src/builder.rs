@@ -0,0 +1,225 @@
+//! Checked construction of [`Sysproxy`].
+
+use crate::{
+    BypassList, Error, ProxyAuth, ProxyEndpoint, ProxyEndpoints, ProxyScheme, Result, Sysproxy,
+};
+use std::net::Ipv6Addr;
+
+/// Fluent builder for [`Sysproxy`]. [`SysproxyBuilder::build`] runs [`Sysproxy::validate`].
+///
+/// Example:
+/// ```
+/// use sysproxy::{ProxyScheme, Sysproxy};
+///
+/// let proxy = Sysproxy::builder()
+///     .host("127.0.0.1")
+///     .port(7890)
+///     .endpoint(ProxyScheme::Socks, "127.0.0.1", 7891)
+///     .build()
+///     .unwrap();
+/// assert!(proxy.enable);
+///
+/// assert!(Sysproxy::builder().host("127.0.0.1").port(0).build().is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SysproxyBuilder {
+    proxy: Sysproxy,
+}
+
+impl Default for SysproxyBuilder {
+    fn default() -> Self {
+        Self {
+            proxy: Sysproxy {
+                enable: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl SysproxyBuilder {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.proxy.host = host.into();
+        self
+    }
+
+    #[inline]
+    pub fn port(mut self, port: u16) -> Self {
+        self.proxy.port = port;
+        self
+    }
+
+    /// Defaults to `true`.
+    #[inline]
+    pub fn enable(mut self, enable: bool) -> Self {
+        self.proxy.enable = enable;
+        self
+    }
+
+    #[inline]
+    pub fn bypass(mut self, bypass: BypassList) -> Self {
+        self.proxy.bypass = bypass;
+        self
+    }
+
+    /// Give `scheme` its own endpoint. Schemes left unset are not proxied.
+    #[inline]
+    pub fn endpoint(mut self, scheme: ProxyScheme, host: impl Into<String>, port: u16) -> Self {
+        self.proxy
+            .endpoints
+            .set(scheme, Some(ProxyEndpoint::new(host, port)));
+        self
+    }
+
+    #[inline]
+    pub fn endpoints(mut self, endpoints: ProxyEndpoints) -> Self {
+        self.proxy.endpoints = endpoints;
+        self
+    }
+
+    #[inline]
+    pub fn auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.proxy.auth = Some(ProxyAuth::new(username, password));
+        self
+    }
+
+    /// Validate and return the proxy.
+    #[inline]
+    pub fn build(self) -> Result<Sysproxy> {
+        self.proxy.validate()?;
+        Ok(self.proxy)
+    }
+}
+
+#[inline]
+fn invalid(field: impl Into<String>, reason: &'static str) -> Error {
+    Error::InvalidConfig {
+        field: field.into(),
+        reason,
+    }
+}
+
+/// Host name or IP address accepted by every backend.
+fn check_host(field: String, host: &str) -> Result<()> {
+    if host.trim().is_empty() {
+        return Err(invalid(field, "empty host"));
+    }
+    if host
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '\'' | '"' | '\\'))
+    {
+        return Err(invalid(field, "contains a quote, backslash or whitespace"));
+    }
+    if host.contains(['/', '@', ',', ';', '[', ']']) {
+        return Err(invalid(field, "not a host name or IP address"));
+    }
+    if host.contains(':') && host.parse::<Ipv6Addr>().is_err() {
+        return Err(invalid(field, "not a host name or IP address"));
+    }
+    Ok(())
+}
+
+impl Sysproxy {
+    #[inline]
+    pub fn builder() -> SysproxyBuilder {
+        SysproxyBuilder::new()
+    }
+
+    /// Check that every endpoint can be written to the system settings.
+    ///
+    /// A disabled proxy is always valid, so it can be used to switch the proxy off.
+    pub fn validate(&self) -> Result<()> {
+        if !self.enable {
+            return Ok(());
+        }
+
+        if self.endpoints.is_empty() {
+            check_host("host".into(), &self.host)?;
+            if self.port == 0 {
+                return Err(invalid("port", "port 0"));
+            }
+        } else {
+            for scheme in ProxyScheme::ALL {
+                let Some(endpoint) = self.endpoints.get(scheme) else {
+                    continue;
+                };
+                let field = format!("endpoints.{}", scheme.as_str());
+                check_host(format!("{field}.host"), &endpoint.host)?;
+                if endpoint.port == 0 {
+                    return Err(invalid(format!("{field}.port"), "port 0"));
+                }
+            }
+        }
+
+        if let Some(auth) = &self.auth {
+            if auth.username.is_empty() {
+                return Err(invalid("auth.username", "empty username"));
+            }
+            if auth
+                .username
+                .chars()
+                .chain(auth.password.chars())
+                .any(char::is_control)
+            {
+                return Err(invalid("auth", "contains a control character"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    fn field_of(result: Result<Sysproxy>) -> String {
+        match result {
+            Err(Error::InvalidConfig { field, .. }) => field,
+            other => format!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn builder_sets_every_field() {
+        let proxy = Sysproxy::builder()
+            .host("::1")
+            .port(7890)
+            .bypass("localhost".parse().unwrap())
+            .auth("user", "secret")
+            .build()
+            .unwrap();
+        assert_eq!(proxy.host, "::1");
+        assert_eq!(proxy.port, 7890);
+        assert!(proxy.enable);
+        assert_eq!(proxy.bypass.len(), 1);
+        assert_eq!(proxy.auth, Some(ProxyAuth::new("user", "secret")));
+    }
+
+    #[test]
+    fn invalid_values_name_the_field() {
+        let builder = || Sysproxy::builder().host("127.0.0.1").port(7890);
+        assert_eq!(field_of(builder().host("").build()), "host");
+        assert_eq!(field_of(builder().host("a'b").build()), "host");
+        assert_eq!(field_of(builder().host("1:2:x").build()), "host");
+        assert_eq!(field_of(builder().port(0).build()), "port");
+        assert_eq!(
+            field_of(builder().endpoint(ProxyScheme::Socks, "h", 0).build()),
+            "endpoints.socks.port"
+        );
+        assert_eq!(field_of(builder().auth("", "x").build()), "auth.username");
+    }
+
+    #[test]
+    fn disabled_proxy_is_always_valid() {
+        assert!(Sysproxy::default().validate().is_ok());
+        assert!(Sysproxy::builder().enable(false).build().is_ok());
+    }
+}
@@ -0,0 +1,377 @@
+//! Typed bypass rules shared by every backend.
+//!
+//! Each backend renders a [`BypassList`] into its own syntax, so one definition behaves the
+//! same on every platform.
+
+use crate::{Error, Result};
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// A single host that should be reached without the proxy.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BypassRule {
+    /// Exact host name or IP address, e.g. `localhost` or `::1`.
+    Host(String),
+    /// Every sub-domain of a domain, written as `*.example.com` or `.example.com`.
+    DomainSuffix(String),
+    /// Any other pattern containing `*`, e.g. `192.168.*`.
+    Wildcard(String),
+    /// IPv4 network, e.g. `127.0.0.1/8`.
+    Ipv4Cidr(Ipv4Addr, u8),
+    /// IPv6 network, e.g. `fe80::/10`.
+    Ipv6Cidr(Ipv6Addr, u8),
+    /// A host on one port only, e.g. `example.com:8080` or `[::1]:8080`.
+    HostPort(String, u16),
+    /// Host names without a dot, written as `<local>`.
+    LocalSimpleNames,
+}
+
+/// Ordered list of [`BypassRule`]s.
+///
+/// With the `serde` feature it is stored as an array of rule strings.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct BypassList(Vec<BypassRule>);
+
+#[inline]
+fn invalid(entry: &str, reason: &'static str) -> Error {
+    Error::InvalidBypass {
+        entry: entry.into(),
+        reason,
+    }
+}
+
+impl BypassRule {
+    /// Parse a single entry in any of the platform syntaxes.
+    pub fn parse(entry: &str) -> Result<BypassRule> {
+        let value = entry.trim();
+
+        if value.is_empty() {
+            return Err(invalid(entry, "empty entry"));
+        }
+        if value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || matches!(c, ',' | ';' | '\'' | '"'))
+        {
+            return Err(invalid(entry, "contains a separator, quote or whitespace"));
+        }
+        if value.eq_ignore_ascii_case("<local>") {
+            return Ok(BypassRule::LocalSimpleNames);
+        }
+
+        if let Some((addr, prefix)) = value.split_once('/') {
+            let prefix = prefix
+                .parse::<u8>()
+                .map_err(|_| invalid(entry, "invalid prefix length"))?;
+            return match addr.parse::<IpAddr>() {
+                Ok(IpAddr::V4(addr)) if prefix <= 32 => Ok(BypassRule::Ipv4Cidr(addr, prefix)),
+                Ok(IpAddr::V6(addr)) if prefix <= 128 => Ok(BypassRule::Ipv6Cidr(addr, prefix)),
+                Ok(_) => Err(invalid(entry, "invalid prefix length")),
+                Err(_) => Err(invalid(entry, "invalid network address")),
+            };
+        }
+
+        let suffix = value.strip_prefix("*.").or_else(|| value.strip_prefix('.'));
+        if let Some(suffix) = suffix {
+            if suffix.contains(':') {
+                return Err(invalid(entry, "a domain suffix can't have a port"));
+            }
+            if !suffix.is_empty() && !suffix.contains('*') {
+                return Ok(BypassRule::DomainSuffix(suffix.into()));
+            }
+        }
+        if value.contains('*') {
+            return Ok(BypassRule::Wildcard(value.into()));
+        }
+
+        if value.parse::<Ipv6Addr>().is_ok() {
+            return Ok(BypassRule::Host(value.into()));
+        }
+
+        if let Some(rest) = value.strip_prefix('[') {
+            let (addr, port) = rest
+                .split_once(']')
+                .ok_or_else(|| invalid(entry, "unclosed `[`"))?;
+            if addr.parse::<Ipv6Addr>().is_err() {
+                return Err(invalid(entry, "invalid IPv6 address"));
+            }
+            if port.is_empty() {
+                return Ok(BypassRule::Host(addr.into()));
+            }
+            let port = port
+                .strip_prefix(':')
+                .and_then(|port| port.parse::<u16>().ok())
+                .ok_or_else(|| invalid(entry, "invalid port"))?;
+            return Ok(BypassRule::HostPort(addr.into(), port));
+        }
+
+        if let Some((host, port)) = value.split_once(':') {
+            if host.is_empty() {
+                return Err(invalid(entry, "missing host"));
+            }
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| invalid(entry, "invalid port"))?;
+            return Ok(BypassRule::HostPort(host.into(), port));
+        }
+
+        Ok(BypassRule::Host(value.into()))
+    }
+}
+
+impl FromStr for BypassRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        BypassRule::parse(s)
+    }
+}
+
+impl fmt::Display for BypassRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BypassRule::Host(host) => write!(f, "{host}"),
+            BypassRule::DomainSuffix(domain) => write!(f, "*.{domain}"),
+            BypassRule::Wildcard(pattern) => write!(f, "{pattern}"),
+            BypassRule::Ipv4Cidr(addr, prefix) => write!(f, "{addr}/{prefix}"),
+            BypassRule::Ipv6Cidr(addr, prefix) => write!(f, "{addr}/{prefix}"),
+            BypassRule::HostPort(host, port) if host.contains(':') => write!(f, "[{host}]:{port}"),
+            BypassRule::HostPort(host, port) => write!(f, "{host}:{port}"),
+            BypassRule::LocalSimpleNames => write!(f, "<local>"),
+        }
+    }
+}
+
+/// Stored in its [`Display`](fmt::Display) form, e.g. `"*.example.com"`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for BypassRule {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for BypassRule {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let entry = String::deserialize(deserializer)?;
+        BypassRule::parse(&entry).map_err(serde::de::Error::custom)
+    }
+}
+
+impl BypassList {
+    #[inline]
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Parse a list separated by `,`, `;` or newlines. Fails on the first invalid entry.
+    pub fn parse(list: &str) -> Result<BypassList> {
+        split_list(list).map(BypassRule::parse).collect()
+    }
+
+    /// Parse a list read back from the system, skipping entries that are not understood.
+    pub fn parse_lossy(list: &str) -> BypassList {
+        Self::from_entries(split_list(list))
+    }
+
+    /// Like [`BypassList::parse_lossy`] for values that are already split.
+    pub fn from_entries<'a>(entries: impl IntoIterator<Item = &'a str>) -> BypassList {
+        entries
+            .into_iter()
+            .filter(|entry| !entry.trim().is_empty())
+            .filter_map(|entry| match BypassRule::parse(entry) {
+                Ok(rule) => Some(rule),
+                Err(e) => {
+                    log::debug!("Skipping bypass entry: {e}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    #[inline]
+    pub fn push(&mut self, rule: BypassRule) {
+        self.0.push(rule);
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[inline]
+    pub fn contains(&self, rule: &BypassRule) -> bool {
+        self.0.contains(rule)
+    }
+
+    #[inline]
+    pub fn iter(&self) -> std::slice::Iter<'_, BypassRule> {
+        self.0.iter()
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[BypassRule] {
+        &self.0
+    }
+
+    /// The list in the `no_proxy` syntax curl, wget, Python, Go and npm agree on.
+    ///
+    /// Domain suffixes become `.example.com`. Wildcards other than `*` and `<local>` have no
+    /// equivalent and are skipped.
+    pub fn to_no_proxy(&self) -> String {
+        self.iter()
+            .filter_map(|rule| match rule {
+                BypassRule::DomainSuffix(domain) => Some(format!(".{domain}")),
+                BypassRule::Wildcard(pattern) if pattern == "*" => Some("*".into()),
+                BypassRule::Wildcard(_) | BypassRule::LocalSimpleNames => {
+                    log::debug!("no_proxy has no equivalent of {rule}, skipping it");
+                    None
+                }
+                rule => Some(rule.to_string()),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+#[inline]
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split([',', ';', '\n'])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+}
+
+impl FromStr for BypassList {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        BypassList::parse(s)
+    }
+}
+
+/// Comma separated, in the canonical syntax of [`BypassRule`].
+impl fmt::Display for BypassList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, rule) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{rule}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Vec<BypassRule>> for BypassList {
+    fn from(rules: Vec<BypassRule>) -> Self {
+        Self(rules)
+    }
+}
+
+impl FromIterator<BypassRule> for BypassList {
+    fn from_iter<I: IntoIterator<Item = BypassRule>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for BypassList {
+    type Item = BypassRule;
+    type IntoIter = std::vec::IntoIter<BypassRule>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a BypassList {
+    type Item = &'a BypassRule;
+    type IntoIter = std::slice::Iter<'a, BypassRule>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn parse_every_rule_kind() {
+        let list = BypassList::parse(
+            "localhost, *.example.com;.corp.local,192.168.*,127.0.0.1/8,fe80::/10,example.com:8080,[::1]:1080,::1,<local>",
+        )
+        .unwrap();
+        assert_eq!(
+            list.as_slice(),
+            &[
+                BypassRule::Host("localhost".into()),
+                BypassRule::DomainSuffix("example.com".into()),
+                BypassRule::DomainSuffix("corp.local".into()),
+                BypassRule::Wildcard("192.168.*".into()),
+                BypassRule::Ipv4Cidr(Ipv4Addr::new(127, 0, 0, 1), 8),
+                BypassRule::Ipv6Cidr("fe80::".parse().unwrap(), 10),
+                BypassRule::HostPort("example.com".into(), 8080),
+                BypassRule::HostPort("::1".into(), 1080),
+                BypassRule::Host("::1".into()),
+                BypassRule::LocalSimpleNames,
+            ]
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let text = "localhost,*.example.com,192.168.*,127.0.0.1/8,fe80::/10,example.com:8080,[::1]:1080,<local>";
+        let list = BypassList::parse(text).unwrap();
+        assert_eq!(list.to_string(), text);
+        assert_eq!(BypassList::parse(&list.to_string()).unwrap(), list);
+    }
+
+    #[test]
+    fn empty_entries_are_ignored() {
+        assert!(BypassList::parse("").unwrap().is_empty());
+        assert_eq!(BypassList::parse("localhost,,").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn invalid_entries_report_the_entry() {
+        for (text, entry) in [
+            ("localhost,127.0.0.1/33", "127.0.0.1/33"),
+            ("example.com:http", "example.com:http"),
+            ("'quoted'", "'quoted'"),
+            ("[::1", "[::1"),
+            ("300.0.0.1/8", "300.0.0.1/8"),
+            ("*.foo.com:8080", "*.foo.com:8080"),
+            (".foo.com:8080", ".foo.com:8080"),
+        ] {
+            assert!(
+                matches!(
+                    BypassList::parse(text),
+                    Err(Error::InvalidBypass { entry: ref e, .. }) if e == entry
+                ),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn lossy_parse_skips_invalid_entries() {
+        let list = BypassList::from_entries(["localhost", "bad entry", ""]);
+        assert_eq!(list.as_slice(), &[BypassRule::Host("localhost".into())]);
+    }
+}
@@ -0,0 +1,342 @@
+//! Batched GNOME writes through dconf's D-Bus writer, behind the `dbus` feature.
+//!
+//! While a batch is open, the gsettings writes of the Linux backend are queued instead of
+//! spawning `gsettings` and `dconf`. Closing the batch sends them to `ca.desrt.dconf.Writer`
+//! in one `Change` call, so they apply together and GNOME sees one change notification.
+//! When the writer can't be reached, the queued writes run through the commands as before.
+
+use crate::{Result, gvariant::GVariant};
+use std::cell::RefCell;
+
+thread_local! {
+    /// Set between [`begin`] and [`flush`] or [`discard`].
+    static BATCH: RefCell<Option<Vec<DconfWrite>>> = const { RefCell::new(None) };
+}
+
+/// One queued key. `None` resets it to the schema default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DconfWrite {
+    pub(crate) schema: String,
+    pub(crate) key: String,
+    /// The key's dconf path, e.g. `/system/proxy/http/host`.
+    pub(crate) path: String,
+    pub(crate) value: Option<GVariant>,
+}
+
+/// Start queuing writes. `false` without the `dbus` feature, while a plan is recorded, or
+/// when a batch is already open.
+pub(crate) fn begin() -> bool {
+    if !cfg!(feature = "dbus") || crate::plan::recording() {
+        return false;
+    }
+    BATCH.with(|batch| {
+        let mut batch = batch.borrow_mut();
+        match *batch {
+            Some(_) => false,
+            None => {
+                *batch = Some(Vec::new());
+                true
+            }
+        }
+    })
+}
+
+/// Queue `write` if a batch is open.
+pub(crate) fn queued(write: impl FnOnce() -> DconfWrite) -> bool {
+    BATCH.with(|batch| match batch.borrow_mut().as_mut() {
+        Some(writes) => {
+            writes.push(write());
+            true
+        }
+        None => false,
+    })
+}
+
+/// Drop the queued writes and close the batch.
+#[inline]
+pub(crate) fn discard() {
+    BATCH.with(|batch| batch.borrow_mut().take());
+}
+
+/// Close the batch and send its writes in one `Change` call, or pass them to `fallback` one
+/// by one when the writer is unavailable.
+pub(crate) fn flush(fallback: impl Fn(&DconfWrite) -> Result<()>) -> Result<()> {
+    let writes = BATCH
+        .with(|batch| batch.borrow_mut().take())
+        .unwrap_or_default();
+    if writes.is_empty() {
+        return Ok(());
+    }
+
+    #[cfg(feature = "dbus")]
+    match writer::connect().and_then(|connection| writer::change(&connection, &writes)) {
+        Ok(()) => return Ok(()),
+        Err(e) => log::warn!("dconf writer unavailable: {e}, using gsettings"),
+    }
+    writes.iter().try_for_each(fallback)
+}
+
+/// Run `apply` in a batch, discarding the queued writes if it fails.
+pub(crate) fn batch(
+    apply: impl FnOnce() -> Result<()>,
+    fallback: impl Fn(&DconfWrite) -> Result<()>,
+) -> Result<()> {
+    if !begin() {
+        return apply();
+    }
+    match apply() {
+        Ok(()) => flush(fallback),
+        Err(e) => {
+            discard();
+            Err(e)
+        }
+    }
+}
+
+#[cfg(feature = "dbus")]
+mod writer {
+    use super::DconfWrite;
+    use crate::{Error, Result, gvariant::GVariant};
+    use std::collections::BTreeMap;
+    use zbus::blocking::Connection;
+    use zvariant::{NATIVE_ENDIAN, Value, serialized::Context};
+
+    const SERVICE: &str = "ca.desrt.dconf";
+    const USER_WRITER: &str = "/ca/desrt/dconf/Writer/user";
+    const INTERFACE: &str = "ca.desrt.dconf.Writer";
+
+    #[inline]
+    fn io_error(e: impl std::error::Error + Send + Sync + 'static) -> Error {
+        Error::Io(std::io::Error::other(e))
+    }
+
+    #[inline]
+    pub(super) fn connect() -> Result<Connection> {
+        Connection::session().map_err(io_error)
+    }
+
+    fn to_value(value: &GVariant) -> Value<'_> {
+        match value {
+            GVariant::Bool(value) => Value::from(*value),
+            // The proxy schemas only have int32 keys.
+            GVariant::Int(value) => match i32::try_from(*value) {
+                Ok(value) => Value::from(value),
+                Err(_) => Value::from(*value),
+            },
+            GVariant::Str(value) => Value::from(value.as_str()),
+            GVariant::StrArray(values) => Value::from(values.clone()),
+        }
+    }
+
+    /// The `Change` argument: an `a{smv}` changeset in GVariant's own serialization.
+    // zvariant 5 deprecates its GVariant format ahead of moving it to a separate crate.
+    #[allow(deprecated)]
+    pub(super) fn changeset(writes: &[DconfWrite]) -> Result<Vec<u8>> {
+        // A later write of the same key wins, as it would with separate writes.
+        let changes = writes
+            .iter()
+            .map(|write| (write.path.as_str(), write.value.as_ref().map(to_value)))
+            .collect::<BTreeMap<_, _>>();
+        let data = zvariant::to_bytes(Context::new_gvariant(NATIVE_ENDIAN, 0), &changes)
+            .map_err(io_error)?;
+        Ok(data.bytes().to_vec())
+    }
+
+    pub(super) fn change(connection: &Connection, writes: &[DconfWrite]) -> Result<()> {
+        let blob = changeset(writes)?;
+        connection
+            .call_method(
+                Some(SERVICE),
+                USER_WRITER,
+                Some(INTERFACE),
+                "Change",
+                &blob.as_slice(),
+            )
+            .map_err(io_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    fn write(key: &str, value: Option<GVariant>) -> DconfWrite {
+        DconfWrite {
+            schema: "org.gnome.system.proxy".into(),
+            key: key.into(),
+            path: format!("/system/proxy/{key}"),
+            value,
+        }
+    }
+
+    #[test]
+    fn batch_queues_until_flushed() {
+        if !begin() {
+            // Built without the writer, nothing is queued.
+            assert!(!queued(|| write("mode", None)));
+            return;
+        }
+        assert!(!begin());
+        assert!(queued(|| write("mode", Some(GVariant::str("manual")))));
+        discard();
+        assert!(!queued(|| write("mode", None)));
+
+        let result = batch(
+            || {
+                queued(|| write("mode", None));
+                Err(crate::Error::NotSupport)
+            },
+            |_| unreachable!(),
+        );
+        assert!(result.is_err());
+        assert!(!queued(|| write("mode", None)));
+    }
+
+    #[test]
+    fn batch_is_off_while_planning() {
+        let plan = crate::plan::record(|| {
+            assert!(!begin());
+            Ok(())
+        });
+        assert!(plan.unwrap().is_empty());
+    }
+
+    #[cfg(feature = "dbus")]
+    mod writer {
+        #![allow(clippy::unwrap_used)]
+        use super::super::{DconfWrite, writer};
+        use super::write;
+        use crate::gvariant::GVariant;
+        use std::{
+            collections::BTreeMap,
+            io::{BufRead, BufReader},
+            process::{Child, Command, Stdio},
+            sync::{Arc, Mutex},
+        };
+        use zvariant::{NATIVE_ENDIAN, OwnedValue, serialized::Context, serialized::Data};
+
+        const BUS_CONFIG: &str = r#"<busconfig>
+  <type>session</type>
+  <listen>unix:tmpdir=/tmp</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#;
+
+        /// A private bus, killed on drop.
+        struct Bus(Child, String);
+
+        impl Drop for Bus {
+            fn drop(&mut self) {
+                let _ = self.0.kill();
+                let _ = self.0.wait();
+            }
+        }
+
+        fn private_bus() -> Option<Bus> {
+            crate::utils::find_in_path("dbus-daemon")?;
+            let dir = crate::utils::tests::TempDir::new("bus");
+            let config = dir.join("bus.conf");
+            std::fs::write(&config, BUS_CONFIG).unwrap();
+            let mut child = Command::new("dbus-daemon")
+                .arg(format!("--config-file={}", config.display()))
+                .args(["--nofork", "--print-address"])
+                .stderr(Stdio::null())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let mut address = String::new();
+            BufReader::new(child.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Some(Bus(child, address.trim().to_string()))
+        }
+
+        /// Stands in for dconf-service, keeping every changeset it receives.
+        struct MockWriter(Arc<Mutex<Vec<Vec<u8>>>>);
+
+        #[zbus::interface(name = "ca.desrt.dconf.Writer")]
+        impl MockWriter {
+            fn change(&self, blob: Vec<u8>) -> String {
+                self.0.lock().unwrap().push(blob);
+                "tag".into()
+            }
+        }
+
+        #[allow(deprecated)]
+        fn decode(blob: &[u8]) -> BTreeMap<String, Option<OwnedValue>> {
+            let data = Data::new(blob, Context::new_gvariant(NATIVE_ENDIAN, 0));
+            data.deserialize().unwrap().0
+        }
+
+        #[test]
+        fn changeset_is_a_gvariant_dict() {
+            let blob = writer::changeset(&[
+                write("mode", Some(GVariant::str("auto"))),
+                write("mode", Some(GVariant::str("manual"))),
+                write("http/port", Some(GVariant::Int(7890))),
+                write("autoconfig-url", None),
+            ])
+            .unwrap();
+            let changes = decode(&blob);
+            assert_eq!(changes.len(), 3);
+            let mode = changes["/system/proxy/mode"].clone().unwrap();
+            assert_eq!(String::try_from(mode).unwrap(), "manual");
+            let port = changes["/system/proxy/http/port"].clone().unwrap();
+            assert_eq!(i32::try_from(port).unwrap(), 7890);
+            assert!(changes["/system/proxy/autoconfig-url"].is_none());
+        }
+
+        #[test]
+        fn writes_go_out_in_one_change_call() {
+            let Some(bus) = private_bus() else {
+                return;
+            };
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let _service = zbus::blocking::connection::Builder::address(bus.1.as_str())
+                .unwrap()
+                .name("ca.desrt.dconf")
+                .unwrap()
+                .serve_at(
+                    "/ca/desrt/dconf/Writer/user",
+                    MockWriter(Arc::clone(&received)),
+                )
+                .unwrap()
+                .build()
+                .unwrap();
+            let client = zbus::blocking::connection::Builder::address(bus.1.as_str())
+                .unwrap()
+                .build()
+                .unwrap();
+
+            let writes: Vec<DconfWrite> = vec![
+                write("mode", Some(GVariant::str("manual"))),
+                write(
+                    "ignore-hosts",
+                    Some(GVariant::StrArray(vec!["localhost".into(), "it's".into()])),
+                ),
+                write("http/use-authentication", Some(GVariant::Bool(false))),
+            ];
+            writer::change(&client, &writes).unwrap();
+
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 1);
+            let changes = decode(&received[0]);
+            let hosts = changes["/system/proxy/ignore-hosts"].clone().unwrap();
+            assert_eq!(
+                Vec::<String>::try_from(hosts).unwrap(),
+                ["localhost", "it's"]
+            );
+            let auth = changes["/system/proxy/http/use-authentication"]
+                .clone()
+                .unwrap();
+            assert!(!bool::try_from(auth).unwrap());
+        }
+    }
+}
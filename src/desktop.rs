@@ -0,0 +1,201 @@
+//! Which desktop environment is running, and so which settings store the Linux backend uses.
+
+use std::{
+    env,
+    sync::{PoisonError, RwLock},
+};
+
+static OVERRIDE: RwLock<Option<DesktopEnvironment>> = RwLock::new(None);
+
+/// A Linux desktop environment.
+///
+/// KDE Plasma keeps the proxy in kioslaverc. The others read `org.gnome.system.proxy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[non_exhaustive]
+pub enum DesktopEnvironment {
+    Gnome,
+    Kde,
+    Cinnamon,
+    Unity,
+    Budgie,
+    Deepin,
+    Cosmic,
+    Lxqt,
+    Xfce,
+    Mate,
+    /// No hint matched.
+    Unknown,
+}
+
+impl DesktopEnvironment {
+    #[inline]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            DesktopEnvironment::Gnome => "gnome",
+            DesktopEnvironment::Kde => "kde",
+            DesktopEnvironment::Cinnamon => "cinnamon",
+            DesktopEnvironment::Unity => "unity",
+            DesktopEnvironment::Budgie => "budgie",
+            DesktopEnvironment::Deepin => "deepin",
+            DesktopEnvironment::Cosmic => "cosmic",
+            DesktopEnvironment::Lxqt => "lxqt",
+            DesktopEnvironment::Xfce => "xfce",
+            DesktopEnvironment::Mate => "mate",
+            DesktopEnvironment::Unknown => "unknown",
+        }
+    }
+
+    /// The override set with [`DesktopEnvironment::set_override`], or the detected desktop.
+    #[inline]
+    pub fn current() -> DesktopEnvironment {
+        let forced = *OVERRIDE.read().unwrap_or_else(PoisonError::into_inner);
+        forced.unwrap_or_else(DesktopEnvironment::detect)
+    }
+
+    /// Use `desktop` instead of detecting it, or detect again with `None`.
+    #[inline]
+    pub fn set_override(desktop: Option<DesktopEnvironment>) {
+        *OVERRIDE.write().unwrap_or_else(PoisonError::into_inner) = desktop;
+    }
+
+    /// Detect the desktop from the session environment.
+    ///
+    /// `XDG_CURRENT_DESKTOP` is a colon-separated list and the first known entry wins, so
+    /// `ubuntu:GNOME` is GNOME and `Budgie:GNOME` is Budgie. Without a match,
+    /// `XDG_SESSION_DESKTOP`, `DESKTOP_SESSION`, `KDE_FULL_SESSION` and
+    /// `GNOME_DESKTOP_SESSION_ID` are tried in that order.
+    #[inline]
+    pub fn detect() -> DesktopEnvironment {
+        Self::detect_from(|name| env::var(name).ok())
+    }
+
+    fn detect_from(var: impl Fn(&str) -> Option<String>) -> DesktopEnvironment {
+        let from_list =
+            var("XDG_CURRENT_DESKTOP").and_then(|list| list.split(':').find_map(Self::from_name));
+        if let Some(desktop) = from_list {
+            return desktop;
+        }
+
+        for name in ["XDG_SESSION_DESKTOP", "DESKTOP_SESSION"] {
+            // DESKTOP_SESSION may be a path such as /usr/share/xsessions/plasma.
+            let session = var(name).and_then(|value| {
+                let session = value.rsplit('/').next().unwrap_or_default();
+                Self::from_session(session)
+            });
+            if let Some(desktop) = session {
+                return desktop;
+            }
+        }
+
+        if var("KDE_FULL_SESSION").is_some_and(|value| value == "true") {
+            return DesktopEnvironment::Kde;
+        }
+        if var("GNOME_DESKTOP_SESSION_ID").is_some_and(|value| !value.is_empty()) {
+            return DesktopEnvironment::Gnome;
+        }
+        DesktopEnvironment::Unknown
+    }
+
+    /// An `XDG_CURRENT_DESKTOP` entry. Vendor entries such as `ubuntu` are skipped.
+    fn from_name(name: &str) -> Option<DesktopEnvironment> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "gnome" | "gnome-classic" | "gnome-flashback" => Some(DesktopEnvironment::Gnome),
+            "kde" => Some(DesktopEnvironment::Kde),
+            "x-cinnamon" | "cinnamon" => Some(DesktopEnvironment::Cinnamon),
+            "unity" => Some(DesktopEnvironment::Unity),
+            "budgie" | "budgie-desktop" => Some(DesktopEnvironment::Budgie),
+            "deepin" | "dde" => Some(DesktopEnvironment::Deepin),
+            "cosmic" => Some(DesktopEnvironment::Cosmic),
+            "lxqt" => Some(DesktopEnvironment::Lxqt),
+            "xfce" => Some(DesktopEnvironment::Xfce),
+            "mate" => Some(DesktopEnvironment::Mate),
+            _ => None,
+        }
+    }
+
+    /// A session name, which is looser: `plasmawayland`, `gnome-xorg`, `budgie-desktop`.
+    fn from_session(session: &str) -> Option<DesktopEnvironment> {
+        let session = session.trim().to_ascii_lowercase();
+        if session.starts_with("plasma") || session.starts_with("kde") {
+            return Some(DesktopEnvironment::Kde);
+        }
+        if session.starts_with("gnome") {
+            return Some(DesktopEnvironment::Gnome);
+        }
+        let name = session.split(['-', '.']).next().unwrap_or_default();
+        Self::from_name(name)
+    }
+}
+
+impl std::fmt::Display for DesktopEnvironment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn detect(vars: &[(&str, &str)]) -> DesktopEnvironment {
+        let vars = vars.iter().copied().collect::<HashMap<_, _>>();
+        DesktopEnvironment::detect_from(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn colon_lists_use_first_known_entry() {
+        for (value, desktop) in [
+            ("KDE", DesktopEnvironment::Kde),
+            ("KDE:plasma", DesktopEnvironment::Kde),
+            ("ubuntu:GNOME", DesktopEnvironment::Gnome),
+            ("X-Cinnamon", DesktopEnvironment::Cinnamon),
+            ("Unity", DesktopEnvironment::Unity),
+            ("Budgie:GNOME", DesktopEnvironment::Budgie),
+            ("Deepin", DesktopEnvironment::Deepin),
+            ("COSMIC", DesktopEnvironment::Cosmic),
+            ("LXQt", DesktopEnvironment::Lxqt),
+            ("pop:GNOME", DesktopEnvironment::Gnome),
+        ] {
+            assert_eq!(
+                detect(&[("XDG_CURRENT_DESKTOP", value)]),
+                desktop,
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn falls_back_to_session_hints() {
+        assert_eq!(
+            detect(&[("DESKTOP_SESSION", "plasmawayland")]),
+            DesktopEnvironment::Kde
+        );
+        assert_eq!(
+            detect(&[("DESKTOP_SESSION", "/usr/share/xsessions/budgie-desktop")]),
+            DesktopEnvironment::Budgie
+        );
+        assert_eq!(
+            detect(&[
+                ("XDG_CURRENT_DESKTOP", "sway"),
+                ("KDE_FULL_SESSION", "true")
+            ]),
+            DesktopEnvironment::Kde
+        );
+        assert_eq!(
+            detect(&[("GNOME_DESKTOP_SESSION_ID", "this-is-deprecated")]),
+            DesktopEnvironment::Gnome
+        );
+        assert_eq!(detect(&[]), DesktopEnvironment::Unknown);
+    }
+
+    #[test]
+    fn override_replaces_detection() {
+        DesktopEnvironment::set_override(Some(DesktopEnvironment::Xfce));
+        assert_eq!(DesktopEnvironment::current(), DesktopEnvironment::Xfce);
+        DesktopEnvironment::set_override(None);
+        assert_eq!(DesktopEnvironment::current(), DesktopEnvironment::detect());
+    }
+}
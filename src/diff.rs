@@ -0,0 +1,322 @@
+//! Field-by-field comparison of two proxy configurations.
+
+use crate::{Autoproxy, BypassRule, ProxyEndpoint, ProxyScheme, Sysproxy, utils::format_host_port};
+use std::fmt;
+
+/// One field that differs between two configurations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ProxyChange {
+    Enable {
+        from: bool,
+        to: bool,
+    },
+    Host {
+        from: String,
+        to: String,
+    },
+    Port {
+        from: u16,
+        to: u16,
+    },
+    /// The endpoint written for `scheme`, reported instead of host and port once either side
+    /// uses per-scheme endpoints.
+    Endpoint {
+        scheme: ProxyScheme,
+        from: Option<ProxyEndpoint>,
+        to: Option<ProxyEndpoint>,
+    },
+    /// The credentials changed. The values are left out so they don't end up in logs.
+    Auth,
+    BypassAdded(BypassRule),
+    BypassRemoved(BypassRule),
+    Url {
+        from: String,
+        to: String,
+    },
+}
+
+#[inline]
+fn endpoint_str(endpoint: &Option<ProxyEndpoint>) -> String {
+    match endpoint {
+        Some(endpoint) => format_host_port(&endpoint.host, endpoint.port),
+        None => "none".into(),
+    }
+}
+
+impl fmt::Display for ProxyChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyChange::Enable { from, to } => write!(f, "enable: {from} -> {to}"),
+            ProxyChange::Host { from, to } => write!(f, "host: {from} -> {to}"),
+            ProxyChange::Port { from, to } => write!(f, "port: {from} -> {to}"),
+            ProxyChange::Endpoint { scheme, from, to } => write!(
+                f,
+                "{}: {} -> {}",
+                scheme.as_str(),
+                endpoint_str(from),
+                endpoint_str(to)
+            ),
+            ProxyChange::Auth => write!(f, "credentials changed"),
+            ProxyChange::BypassAdded(rule) => write!(f, "bypass: +{rule}"),
+            ProxyChange::BypassRemoved(rule) => write!(f, "bypass: -{rule}"),
+            ProxyChange::Url { from, to } => write!(f, "url: {from} -> {to}"),
+        }
+    }
+}
+
+/// What changed from one configuration to another, see [`Sysproxy::diff`].
+///
+/// Example:
+/// ```
+/// use sysproxy::{ProxyChange, Sysproxy};
+///
+/// let old = Sysproxy::builder().host("127.0.0.1").port(7890).build().unwrap();
+/// let new = Sysproxy::builder().host("127.0.0.1").port(7897).build().unwrap();
+/// let diff = old.diff(&new);
+/// assert_eq!(diff.changes(), [ProxyChange::Port { from: 7890, to: 7897 }]);
+/// assert_eq!(diff.to_string(), "port: 7890 -> 7897");
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct ProxyDiff {
+    changes: Vec<ProxyChange>,
+}
+
+impl ProxyDiff {
+    #[inline]
+    pub fn changes(&self) -> &[ProxyChange] {
+        &self.changes
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    #[inline]
+    pub fn enable_changed(&self) -> bool {
+        self.changes
+            .iter()
+            .any(|change| matches!(change, ProxyChange::Enable { .. }))
+    }
+
+    #[inline]
+    pub fn bypass_changed(&self) -> bool {
+        self.changes.iter().any(|change| {
+            matches!(
+                change,
+                ProxyChange::BypassAdded(_) | ProxyChange::BypassRemoved(_)
+            )
+        })
+    }
+
+    /// `self` without the changes `keep` rejects.
+    #[inline]
+    pub(crate) fn retain(mut self, keep: impl FnMut(&ProxyChange) -> bool) -> ProxyDiff {
+        self.changes.retain(keep);
+        self
+    }
+
+    /// Whether the value stored for `scheme` has to be written again.
+    pub fn scheme_changed(&self, scheme: ProxyScheme) -> bool {
+        self.changes.iter().any(|change| match change {
+            ProxyChange::Host { .. } | ProxyChange::Port { .. } => scheme != ProxyScheme::Ftp,
+            ProxyChange::Endpoint {
+                scheme: changed, ..
+            } => *changed == scheme,
+            ProxyChange::Auth => true,
+            _ => false,
+        })
+    }
+}
+
+impl fmt::Display for ProxyDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a ProxyDiff {
+    type Item = &'a ProxyChange;
+    type IntoIter = std::slice::Iter<'a, ProxyChange>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.changes.iter()
+    }
+}
+
+impl Sysproxy {
+    /// The fields that differ from `self` to `other`.
+    ///
+    /// Bypass rules are compared as a set, so reordering them is not a change.
+    pub fn diff(&self, other: &Sysproxy) -> ProxyDiff {
+        let mut changes = Vec::new();
+
+        if self.enable != other.enable {
+            changes.push(ProxyChange::Enable {
+                from: self.enable,
+                to: other.enable,
+            });
+        }
+
+        if self.endpoints.is_empty() && other.endpoints.is_empty() {
+            if self.host != other.host {
+                changes.push(ProxyChange::Host {
+                    from: self.host.clone(),
+                    to: other.host.clone(),
+                });
+            }
+            if self.port != other.port {
+                changes.push(ProxyChange::Port {
+                    from: self.port,
+                    to: other.port,
+                });
+            }
+        } else {
+            for scheme in ProxyScheme::ALL {
+                let (from, to) = (self.endpoint(scheme), other.endpoint(scheme));
+                if from != to {
+                    changes.push(ProxyChange::Endpoint { scheme, from, to });
+                }
+            }
+        }
+
+        if self.auth != other.auth {
+            changes.push(ProxyChange::Auth);
+        }
+
+        for rule in other.bypass.iter() {
+            if !self.bypass.contains(rule) {
+                changes.push(ProxyChange::BypassAdded(rule.clone()));
+            }
+        }
+        for rule in self.bypass.iter() {
+            if !other.bypass.contains(rule) {
+                changes.push(ProxyChange::BypassRemoved(rule.clone()));
+            }
+        }
+
+        ProxyDiff { changes }
+    }
+}
+
+impl Autoproxy {
+    /// The fields that differ from `self` to `other`.
+    pub fn diff(&self, other: &Autoproxy) -> ProxyDiff {
+        let mut changes = Vec::new();
+        if self.enable != other.enable {
+            changes.push(ProxyChange::Enable {
+                from: self.enable,
+                to: other.enable,
+            });
+        }
+        if self.url != other.url {
+            changes.push(ProxyChange::Url {
+                from: self.url.clone(),
+                to: other.url.clone(),
+            });
+        }
+        ProxyDiff { changes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use crate::ProxyAuth;
+
+    fn proxy() -> Sysproxy {
+        Sysproxy::builder()
+            .host("127.0.0.1")
+            .port(7890)
+            .bypass("localhost,127.0.0.1/8".parse().unwrap())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn equal_configs_have_no_diff() {
+        assert!(proxy().diff(&proxy()).is_empty());
+
+        let mut reordered = proxy();
+        reordered.bypass = "127.0.0.1/8,localhost".parse().unwrap();
+        assert!(proxy().diff(&reordered).is_empty());
+    }
+
+    #[test]
+    fn diff_lists_changed_fields() {
+        let mut changed = proxy();
+        changed.enable = false;
+        changed.host = "10.0.0.1".into();
+        changed.bypass = "localhost,*.lan".parse().unwrap();
+
+        let diff = proxy().diff(&changed);
+        assert_eq!(
+            diff.to_string(),
+            "enable: true -> false, host: 127.0.0.1 -> 10.0.0.1, bypass: +*.lan, \
+             bypass: -127.0.0.1/8"
+        );
+        assert!(diff.enable_changed());
+        assert!(diff.bypass_changed());
+        assert!(diff.scheme_changed(ProxyScheme::Http));
+        assert!(!diff.scheme_changed(ProxyScheme::Ftp));
+    }
+
+    #[test]
+    fn endpoints_are_compared_per_scheme() {
+        let mut changed = proxy();
+        changed.endpoints.set(
+            ProxyScheme::Socks,
+            Some(ProxyEndpoint::new("127.0.0.1", 7891)),
+        );
+        changed.endpoints.set(
+            ProxyScheme::Http,
+            Some(ProxyEndpoint::new("127.0.0.1", 7890)),
+        );
+        changed.endpoints.set(
+            ProxyScheme::Https,
+            Some(ProxyEndpoint::new("127.0.0.1", 7890)),
+        );
+        changed.auth = Some(ProxyAuth::new("user", "secret"));
+
+        let diff = proxy().diff(&changed);
+        assert_eq!(
+            diff.changes(),
+            [
+                ProxyChange::Endpoint {
+                    scheme: ProxyScheme::Socks,
+                    from: Some(ProxyEndpoint::new("127.0.0.1", 7890)),
+                    to: Some(ProxyEndpoint::new("127.0.0.1", 7891)),
+                },
+                ProxyChange::Auth,
+            ]
+        );
+        assert!(!diff.to_string().contains("secret"));
+    }
+
+    #[test]
+    fn autoproxy_diff() {
+        let from = Autoproxy {
+            url: "http://127.0.0.1/pac".into(),
+            enable: true,
+        };
+        let to = Autoproxy {
+            url: "http://127.0.0.1/other".into(),
+            enable: true,
+        };
+        assert_eq!(
+            from.diff(&to).to_string(),
+            "url: http://127.0.0.1/pac -> http://127.0.0.1/other"
+        );
+    }
+}
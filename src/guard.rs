@@ -10,9 +10,10 @@ use std::{
 use log::{debug, error};
 use tokio::sync::Notify;
 
-use crate::{Autoproxy, Sysproxy};
+use crate::{Autoproxy, ProxyBackend, Sysproxy, SystemBackend};
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GuardType {
     None,
     Sysproxy(Sysproxy),
@@ -21,6 +22,7 @@ pub enum GuardType {
 
 #[repr(u8)]
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GuardState {
     Running = 0,
     Stopped,
@@ -82,11 +84,13 @@ impl fmt::Display for GuardState {
 struct TaskConfig {
     guard_type: GuardType,
     interval: Duration,
+    backend: Arc<dyn ProxyBackend>,
 }
 
 pub struct GuardMonitor {
     guard_type: GuardType,
     interval: Duration,
+    backend: Arc<dyn ProxyBackend>,
     notify: Arc<Notify>,
     guard_stat: Arc<AtomicU8>,
 }
@@ -101,6 +105,16 @@ impl Drop for GuardMonitor {
 impl GuardMonitor {
     #[inline]
     pub fn new(guard_type: GuardType, interval: Duration) -> Self {
+        Self::with_backend(guard_type, interval, Arc::new(SystemBackend))
+    }
+
+    /// Guard the settings of `backend` instead of the running system.
+    #[inline]
+    pub fn with_backend(
+        guard_type: GuardType,
+        interval: Duration,
+        backend: Arc<dyn ProxyBackend>,
+    ) -> Self {
         debug!("Create GuardMonitor with interval: {:?}", interval);
         debug!(
             "GuardType: {:?}",
@@ -113,6 +127,7 @@ impl GuardMonitor {
         Self {
             guard_type,
             interval,
+            backend,
             notify: Arc::new(Notify::new()),
             guard_stat: Arc::new(AtomicU8::new(GuardState::Stopped as u8)),
         }
@@ -152,32 +167,28 @@ impl GuardMonitor {
     }
 
     #[inline]
-    fn guard_sysproxy_static(sysproxy: &Sysproxy) {
-        if let Ok(actually_sysproxy) = Sysproxy::get_system_proxy()
-            && &actually_sysproxy != sysproxy
-        {
-            debug!(
-                "Sysproxy settings do not match! Expected: {:?}, Actual: {:?}",
-                sysproxy, actually_sysproxy
-            );
-            debug!("Resetting Sysproxy to: {:?}", sysproxy);
-            if let Err(e) = sysproxy.set_system_proxy() {
+    fn guard_sysproxy_static(backend: &dyn ProxyBackend, sysproxy: &Sysproxy) {
+        let Ok(actually_sysproxy) = backend.get_system_proxy() else {
+            return;
+        };
+        let diff = backend.system_proxy_diff(&actually_sysproxy, sysproxy);
+        if !diff.is_empty() {
+            debug!("Sysproxy settings do not match, restoring: {diff}");
+            if let Err(e) = backend.set_system_proxy_diff(sysproxy, &diff) {
                 error!("Failed to set system proxy: {:?}", e);
             }
         }
     }
 
     #[inline]
-    fn guard_autoproxy_static(autoproxy: &Autoproxy) {
-        if let Ok(actually_autoproxy) = Autoproxy::get_auto_proxy()
-            && &actually_autoproxy != autoproxy
-        {
-            debug!(
-                "Autoproxy settings do not match! Expected: {:?}, Actual: {:?}",
-                autoproxy, actually_autoproxy
-            );
-            debug!("Resetting Autoproxy to: {:?}", autoproxy);
-            if let Err(e) = autoproxy.set_auto_proxy() {
+    fn guard_autoproxy_static(backend: &dyn ProxyBackend, autoproxy: &Autoproxy) {
+        let Ok(actually_autoproxy) = backend.get_auto_proxy() else {
+            return;
+        };
+        let diff = actually_autoproxy.diff(autoproxy);
+        if !diff.is_empty() {
+            debug!("Autoproxy settings do not match, restoring: {diff}");
+            if let Err(e) = backend.set_auto_proxy(autoproxy) {
                 error!("Failed to set auto proxy: {:?}", e);
             }
         }
@@ -216,6 +227,7 @@ impl GuardMonitor {
         let config = TaskConfig {
             guard_type: self.guard_type.clone(),
             interval: self.interval,
+            backend: Arc::clone(&self.backend),
         };
         let guard_stat = Arc::clone(&self.guard_stat);
         let notify = Arc::clone(&self.notify);
@@ -248,11 +260,11 @@ impl GuardMonitor {
                     match &config.guard_type {
                         GuardType::Sysproxy(sysproxy) => {
                             debug!("GuardMonitor checking Sysproxy: {:?}", sysproxy);
-                            Self::guard_sysproxy_static(sysproxy);
+                            Self::guard_sysproxy_static(config.backend.as_ref(), sysproxy);
                         }
                         GuardType::Autoproxy(autoproxy) => {
                             debug!("GuardMonitor checking Autoproxy: {:?}", autoproxy);
-                            Self::guard_autoproxy_static(autoproxy);
+                            Self::guard_autoproxy_static(config.backend.as_ref(), autoproxy);
                         }
                         GuardType::None => {
                             debug!("GuardMonitor has no GuardType set, skipping check.");
@@ -288,7 +300,9 @@ impl GuardMonitor {
 
 #[cfg(test)]
 mod tests {
+    #![allow(clippy::unwrap_used)]
     use super::*;
+    use crate::{MockBackend, MockOperation};
     use std::time::Duration;
 
     #[test]
@@ -312,7 +326,8 @@ mod tests {
             enable: true,
             host: "127.0.0.1".to_string(),
             port: 8080,
-            bypass: "localhost".to_string(),
+            bypass: "localhost".parse().unwrap(),
+            ..Default::default()
         };
 
         let guard_monitor =
@@ -382,7 +397,8 @@ mod tests {
             enable: true,
             host: "192.168.1.1".to_string(),
             port: 3128,
-            bypass: "*.local".to_string(),
+            bypass: "*.local".parse().unwrap(),
+            ..Default::default()
         };
 
         guard_monitor.set_guard_type(GuardType::Sysproxy(new_sysproxy));
@@ -413,7 +429,8 @@ mod tests {
             enable: true,
             host: "proxy.example.com".to_string(),
             port: 8888,
-            bypass: "localhost,127.0.0.1".to_string(),
+            bypass: "localhost,127.0.0.1".parse().unwrap(),
+            ..Default::default()
         };
 
         let guard_monitor =
@@ -449,7 +466,8 @@ mod tests {
             enable: false,
             host: "127.0.0.1".to_string(),
             port: 8080,
-            bypass: "localhost".to_string(),
+            bypass: "localhost".parse().unwrap(),
+            ..Default::default()
         };
 
         let guard_monitor = GuardMonitor::new(
@@ -462,6 +480,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_guard_type_debug_hides_password() {
+        let guard_type = GuardType::Sysproxy(Sysproxy {
+            enable: true,
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            auth: Some(crate::ProxyAuth::new("alice", "hunter2")),
+            ..Default::default()
+        });
+
+        assert!(!format!("{:?}", guard_type).contains("hunter2"));
+    }
+
     // State Machine Tests
     #[test]
     fn test_initial_state() {
@@ -765,4 +796,97 @@ mod tests {
         tokio::time::sleep(Duration::from_millis(50)).await;
         assert!(monitor.get_state().is_stopped());
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_guard_type_serde_round_trip() {
+        let guard_type = GuardType::Autoproxy(Autoproxy {
+            url: "http://example.com/proxy.pac".to_string(),
+            enable: true,
+        });
+        let json = serde_json::to_string(&guard_type).unwrap();
+        assert_eq!(
+            serde_json::from_str::<GuardType>(&json).unwrap(),
+            guard_type
+        );
+
+        let state: GuardState = serde_json::from_str(r#""NeedRestart""#).unwrap();
+        assert!(matches!(state, GuardState::NeedRestart));
+    }
+
+    #[tokio::test]
+    async fn test_guard_restores_changed_sysproxy() {
+        let expected = Sysproxy::builder()
+            .host("127.0.0.1")
+            .port(7890)
+            .build()
+            .unwrap();
+        let backend = Arc::new(MockBackend::with_state(
+            expected.clone(),
+            Autoproxy::default(),
+        ));
+        let monitor = GuardMonitor::with_backend(
+            GuardType::Sysproxy(expected.clone()),
+            Duration::from_millis(20),
+            Arc::<MockBackend>::clone(&backend),
+        );
+        monitor.start();
+
+        backend.replace_sysproxy(Sysproxy::default());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        monitor.stop();
+
+        assert_eq!(backend.sysproxy(), expected);
+        assert!(backend.calls(MockOperation::SetSystemProxy) >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_guard_ignores_auth_the_backend_drops() {
+        let expected = Sysproxy::builder()
+            .host("127.0.0.1")
+            .port(7890)
+            .auth("user", "secret")
+            .build()
+            .unwrap();
+        let backend = Arc::new(MockBackend::new());
+        backend.drop_auth();
+        backend.set_system_proxy(&expected).unwrap();
+        assert_eq!(backend.sysproxy().auth, None);
+
+        let monitor = GuardMonitor::with_backend(
+            GuardType::Sysproxy(expected),
+            Duration::from_millis(20),
+            Arc::<MockBackend>::clone(&backend),
+        );
+        monitor.start();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        monitor.stop();
+
+        assert!(backend.calls(MockOperation::GetSystemProxy) >= 2);
+        assert_eq!(backend.calls(MockOperation::SetSystemProxy), 1);
+    }
+
+    #[tokio::test]
+    async fn test_guard_survives_backend_failures() {
+        let expected = Autoproxy {
+            url: "http://example.com/proxy.pac".to_string(),
+            enable: true,
+        };
+        let backend = Arc::new(MockBackend::new());
+        backend.fail_always(MockOperation::GetAutoProxy);
+        let monitor = GuardMonitor::with_backend(
+            GuardType::Autoproxy(expected.clone()),
+            Duration::from_millis(20),
+            Arc::<MockBackend>::clone(&backend),
+        );
+        monitor.start();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(monitor.get_state().is_running());
+        assert_eq!(backend.calls(MockOperation::SetAutoProxy), 0);
+
+        backend.clear_failures();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        monitor.stop();
+        assert_eq!(backend.autoproxy(), expected);
+    }
 }
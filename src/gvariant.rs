@@ -0,0 +1,292 @@
+//! The GVariant text format used by gsettings and dconf, for the value types the proxy
+//! schemas use: strings and enums, string arrays, integers and booleans.
+
+use crate::{Error, Result};
+use std::{fmt, iter::Peekable, str::Chars};
+
+/// A parsed GVariant value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum GVariant {
+    Bool(bool),
+    Int(i64),
+    /// A string, or an enum nick such as `manual`.
+    Str(String),
+    StrArray(Vec<String>),
+}
+
+impl GVariant {
+    #[inline]
+    pub(crate) fn str(value: impl Into<String>) -> GVariant {
+        GVariant::Str(value.into())
+    }
+
+    /// Parse one value as printed by `gsettings get` or `dconf read`.
+    pub(crate) fn parse(text: &str) -> Result<GVariant> {
+        let mut parser = Parser {
+            chars: text.trim().chars().peekable(),
+        };
+        let value = parser.value().ok_or_else(|| Error::ParseStr(text.into()))?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(value),
+            Some(_) => Err(Error::ParseStr(text.into())),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn into_string(self) -> Result<String> {
+        match self {
+            GVariant::Str(value) => Ok(value),
+            other => Err(Error::ParseStr(other.to_string())),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn into_strv(self) -> Result<Vec<String>> {
+        match self {
+            GVariant::StrArray(values) => Ok(values),
+            other => Err(Error::ParseStr(other.to_string())),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn to_int(&self) -> Result<i64> {
+        match self {
+            GVariant::Int(value) => Ok(*value),
+            other => Err(Error::ParseStr(other.to_string())),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn to_bool(&self) -> Result<bool> {
+        match self {
+            GVariant::Bool(value) => Ok(*value),
+            other => Err(Error::ParseStr(other.to_string())),
+        }
+    }
+}
+
+impl From<bool> for GVariant {
+    #[inline]
+    fn from(value: bool) -> Self {
+        GVariant::Bool(value)
+    }
+}
+
+impl From<u16> for GVariant {
+    #[inline]
+    fn from(value: u16) -> Self {
+        GVariant::Int(value.into())
+    }
+}
+
+impl From<&str> for GVariant {
+    #[inline]
+    fn from(value: &str) -> Self {
+        GVariant::Str(value.into())
+    }
+}
+
+impl From<Vec<String>> for GVariant {
+    #[inline]
+    fn from(values: Vec<String>) -> Self {
+        GVariant::StrArray(values)
+    }
+}
+
+/// Serialized so that [`GVariant::parse`] and GLib read back the same value.
+impl fmt::Display for GVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GVariant::Bool(value) => write!(f, "{value}"),
+            GVariant::Int(value) => write!(f, "{value}"),
+            GVariant::Str(value) => write_str(f, value),
+            // Without the annotation an empty array has no type.
+            GVariant::StrArray(values) if values.is_empty() => f.write_str("@as []"),
+            GVariant::StrArray(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write_str(f, value)?;
+                }
+                f.write_str("]")
+            }
+        }
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_str("'")?;
+    for c in value.chars() {
+        match c {
+            '\\' => f.write_str("\\\\")?,
+            '\'' => f.write_str("\\'")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("'")
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+        {
+            word.push(c);
+        }
+        word
+    }
+
+    fn value(&mut self) -> Option<GVariant> {
+        self.skip_whitespace();
+        match self.chars.peek()? {
+            '\'' | '"' => self.string().map(GVariant::Str),
+            '[' => self.array(),
+            // A type annotation such as `@as`, which only matters for empty arrays.
+            '@' => {
+                self.chars.next();
+                while self.chars.next_if(|c| !c.is_whitespace()).is_some() {}
+                self.value()
+            }
+            _ => match self.word().as_str() {
+                "true" => Some(GVariant::Bool(true)),
+                "false" => Some(GVariant::Bool(false)),
+                // Type keywords dconf prints before integers that are not int32.
+                "byte" | "int16" | "uint16" | "int32" | "uint32" | "int64" | "uint64" => self
+                    .value()
+                    .filter(|value| matches!(value, GVariant::Int(_))),
+                word => word.parse().ok().map(GVariant::Int),
+            },
+        }
+    }
+
+    fn array(&mut self) -> Option<GVariant> {
+        self.chars.next();
+        let mut values = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.chars.next_if_eq(&']').is_some() {
+                return Some(GVariant::StrArray(values));
+            }
+            if !values.is_empty() {
+                self.chars.next_if_eq(&',')?;
+                self.skip_whitespace();
+            }
+            values.push(self.string()?);
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let quote = self.chars.next().filter(|c| matches!(c, '\'' | '"'))?;
+        let mut value = String::new();
+        loop {
+            match self.chars.next()? {
+                c if c == quote => return Some(value),
+                '\\' => value.push(self.escape()?),
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Option<char> {
+        Some(match self.chars.next()? {
+            'a' => '\u{7}',
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'v' => '\u{b}',
+            'u' => self.hex(4)?,
+            'U' => self.hex(8)?,
+            // `\\`, `\'`, `\"` and anything else stand for themselves.
+            c => c,
+        })
+    }
+
+    fn hex(&mut self, digits: usize) -> Option<char> {
+        let hex = (0..digits)
+            .map(|_| self.chars.next())
+            .collect::<Option<String>>()?;
+        char::from_u32(u32::from_str_radix(&hex, 16).ok()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn parses_gsettings_output() {
+        assert_eq!(
+            GVariant::parse("'manual'").unwrap(),
+            GVariant::str("manual")
+        );
+        assert_eq!(GVariant::parse("7890").unwrap(), GVariant::Int(7890));
+        assert_eq!(GVariant::parse("uint32 7").unwrap(), GVariant::Int(7));
+        assert_eq!(GVariant::parse("true\n").unwrap(), GVariant::Bool(true));
+        assert_eq!(GVariant::parse("\"it's\"").unwrap(), GVariant::str("it's"));
+        assert_eq!(
+            GVariant::parse("['localhost', '127.0.0.0/8', '::1']").unwrap(),
+            GVariant::StrArray(vec!["localhost".into(), "127.0.0.0/8".into(), "::1".into()])
+        );
+        assert_eq!(
+            GVariant::parse("@as []").unwrap(),
+            GVariant::StrArray(Vec::new())
+        );
+        assert_eq!(
+            GVariant::parse("'\\u00e9\\t\\\\'").unwrap(),
+            GVariant::str("é\t\\")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        for text in [
+            "",
+            "'open",
+            "['a' 'b']",
+            "['a',]",
+            "'a' 'b'",
+            "manual",
+            "[1]",
+        ] {
+            assert!(GVariant::parse(text).is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn values_round_trip() {
+        for value in [
+            GVariant::str("it's a \"test\""),
+            GVariant::str("back\\slash, ] and \u{1}"),
+            GVariant::str("ünïcødé 代理"),
+            GVariant::str(""),
+            GVariant::StrArray(vec!["a'], 'b".into(), "*.example.com".into()]),
+            GVariant::StrArray(Vec::new()),
+            GVariant::Int(-1),
+            GVariant::Bool(false),
+        ] {
+            assert_eq!(GVariant::parse(&value.to_string()).unwrap(), value);
+        }
+        assert_eq!(GVariant::str("it's").to_string(), "'it\\'s'");
+        assert_eq!(GVariant::StrArray(Vec::new()).to_string(), "@as []");
+    }
+}
@@ -0,0 +1,484 @@
+//! Reader and writer for KConfig INI files such as kioslaverc.
+//!
+//! Lines that are not changed are written back byte for byte, so comments, unknown groups and
+//! keys in other locales survive a rewrite.
+
+use crate::{Error, Result, utils::write_atomic};
+use std::{env, fs, io, path::Path};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    /// Comment, blank line, or anything this parser does not understand.
+    Other(String),
+    Group {
+        raw: String,
+        name: String,
+    },
+    Entry(Entry),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    raw: String,
+    key: String,
+    /// Text between the brackets after the key: a locale, or `$e`, `$i` and the like.
+    options: Option<String>,
+    value: String,
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Entry> {
+        let (key, value) = line.split_once('=')?;
+        let key = key.trim();
+        let (key, options) = match key.find('[') {
+            Some(i) if key.ends_with(']') => (&key[..i], Some(&key[i + 1..key.len() - 1])),
+            _ => (key, None),
+        };
+        if key.is_empty() {
+            return None;
+        }
+        Some(Entry {
+            raw: line.to_string(),
+            key: key.to_string(),
+            options: options.map(str::to_string),
+            value: value.trim_start().to_string(),
+        })
+    }
+
+    /// `$e`, `$i` or both, as in `key[$ei]`.
+    #[inline]
+    fn has_flag(&self, flag: char) -> bool {
+        self.options
+            .as_deref()
+            .and_then(|options| options.strip_prefix('$'))
+            .is_some_and(|flags| flags.contains(flag))
+    }
+
+    /// A plain entry, not one for a specific locale.
+    #[inline]
+    fn is_default_locale(&self) -> bool {
+        self.options
+            .as_deref()
+            .is_none_or(|options| options.starts_with('$'))
+    }
+}
+
+/// A parsed KConfig file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct KConfig {
+    lines: Vec<Line>,
+}
+
+impl KConfig {
+    pub(crate) fn parse(text: &str) -> KConfig {
+        let lines = text
+            .lines()
+            .map(|line| {
+                let trimmed = line.trim();
+                if trimmed.starts_with('#') || trimmed.is_empty() {
+                    return Line::Other(line.to_string());
+                }
+                if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                    // `[$i]` on its own locks the rest of the file, it is not a group.
+                    if trimmed == "[$i]" {
+                        return Line::Other(line.to_string());
+                    }
+                    let name = trimmed.strip_suffix("[$i]").unwrap_or(trimmed);
+                    return Line::Group {
+                        raw: line.to_string(),
+                        name: name[1..name.len() - 1].to_string(),
+                    };
+                }
+                match Entry::parse(line) {
+                    Some(entry) => Line::Entry(entry),
+                    None => Line::Other(line.to_string()),
+                }
+            })
+            .collect();
+        KConfig { lines }
+    }
+
+    /// Read `path`, an empty config when the file does not exist.
+    pub(crate) fn load(path: &Path) -> Result<KConfig> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(KConfig::parse(&text)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(KConfig::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write through a temporary file in the same directory, then rename it over `path`.
+    #[inline]
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, self.to_string().as_bytes())
+    }
+
+    /// The entry for `key` in `group`. Later entries override earlier ones, like in KConfig.
+    fn find(&self, group: &str, key: &str) -> Option<usize> {
+        let mut current = "";
+        let mut found = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Group { name, .. } => current = name,
+                Line::Entry(entry)
+                    if current == group && entry.key == key && entry.is_default_locale() =>
+                {
+                    found = Some(i);
+                }
+                _ => {}
+            }
+        }
+        found
+    }
+
+    /// The unescaped value, with `$e` entries expanded. `None` when the key is not set.
+    pub(crate) fn get(&self, group: &str, key: &str) -> Option<String> {
+        let Line::Entry(entry) = &self.lines[self.find(group, key)?] else {
+            return None;
+        };
+        let value = unescape(&entry.value);
+        Some(match entry.has_flag('e') {
+            true => expand_env(&value),
+            false => value,
+        })
+    }
+
+    /// Whether `key` in `group` is locked by `[$i]` on the entry, its group or the file.
+    fn is_immutable(&self, group: &str, key: &str) -> bool {
+        let mut file_locked = false;
+        let mut current = None;
+        for line in &self.lines {
+            match line {
+                Line::Other(raw) if raw.trim() == "[$i]" && current.is_none() => {
+                    file_locked = true;
+                }
+                Line::Group { raw, name } => {
+                    current = Some(name.as_str());
+                    if name == group && (file_locked || raw.trim().ends_with("[$i]")) {
+                        return true;
+                    }
+                }
+                Line::Entry(entry)
+                    if current == Some(group) && entry.key == key && entry.has_flag('i') =>
+                {
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        // A group added at the end is still under a file-level lock.
+        file_locked
+    }
+
+    /// [`Error::Immutable`] when KConfig itself would refuse to change `key` in `group`.
+    #[inline]
+    fn check_mutable(&self, group: &str, key: &str) -> Result<()> {
+        match self.is_immutable(group, key) {
+            true => Err(Error::Immutable {
+                group: group.into(),
+                key: key.into(),
+            }),
+            false => Ok(()),
+        }
+    }
+
+    /// Set `key` in `group`, adding the group at the end if it is missing.
+    ///
+    /// Immutable (`$i`) entries, groups and files are refused with [`Error::Immutable`].
+    pub(crate) fn set(&mut self, group: &str, key: &str, value: &str) -> Result<()> {
+        self.check_mutable(group, key)?;
+        let line = format!("{key}={}", escape(value));
+        let entry = Entry::parse(&line).ok_or_else(|| Error::ParseStr(key.into()))?;
+
+        if let Some(i) = self.find(group, key) {
+            self.lines[i] = Line::Entry(entry);
+            return Ok(());
+        }
+
+        match self.group_end(group) {
+            Some(end) => self.lines.insert(end, Line::Entry(entry)),
+            None => {
+                if self
+                    .lines
+                    .last()
+                    .is_some_and(|line| !matches!(line, Line::Other(s) if s.trim().is_empty()))
+                {
+                    self.lines.push(Line::Other(String::new()));
+                }
+                self.lines.push(Line::Group {
+                    raw: format!("[{group}]"),
+                    name: group.to_string(),
+                });
+                self.lines.push(Line::Entry(entry));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove `key` from `group`, refused like [`KConfig::set`] when it is immutable.
+    pub(crate) fn delete(&mut self, group: &str, key: &str) -> Result<()> {
+        self.check_mutable(group, key)?;
+        while let Some(i) = self.find(group, key) {
+            self.lines.remove(i);
+        }
+        Ok(())
+    }
+
+    /// Index after the last entry of `group`, so new keys go before trailing blank lines.
+    fn group_end(&self, group: &str) -> Option<usize> {
+        let mut current: Option<&str> = None;
+        let mut end = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Group { name, .. } => {
+                    current = Some(name);
+                    if name == group {
+                        end = Some(i + 1);
+                    }
+                }
+                Line::Entry(_) if current == Some(group) => end = Some(i + 1),
+                _ => {}
+            }
+        }
+        end
+    }
+}
+
+impl std::fmt::Display for KConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            let raw = match line {
+                Line::Other(raw) | Line::Group { raw, .. } | Line::Entry(Entry { raw, .. }) => raw,
+            };
+            writeln!(f, "{raw}")?;
+        }
+        Ok(())
+    }
+}
+
+/// KConfig value escaping: backslash, control characters, and spaces at either end.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            ' ' if i == 0 || i == last => escaped.push_str("\\s"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let value = value.trim_end();
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('s') => unescaped.push(' '),
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) => unescaped.push(char::from(byte)),
+                    Err(_) => {
+                        unescaped.push_str("\\x");
+                        unescaped.push_str(&hex);
+                    }
+                }
+            }
+            // `\;` and `\,` belong to list values, keep them for the caller.
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// `$e` expansion of `$VAR`, `${VAR}` and `$$`. `$(command)` is left as written.
+fn expand_env(value: &str) -> String {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+        } else if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => {
+                    expanded.push_str(&env::var(&braced[..end]).unwrap_or_default());
+                    rest = &braced[end + 1..];
+                }
+                None => expanded.push('$'),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            match end {
+                0 => expanded.push('$'),
+                _ => {
+                    expanded.push_str(&env::var(&rest[..end]).unwrap_or_default());
+                    rest = &rest[end..];
+                }
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use crate::utils::tests::TempDir;
+
+    const KIOSLAVERC: &str = "\
+# written by hand
+[$i]
+[Proxy Settings]
+ProxyType=1
+httpProxy=http://127.0.0.1 7890
+NoProxyFor[$e]=localhost,$SYSPROXY_KCONFIG_TEST_HOST
+Proxy Config Script[de]=http://example.de/pac
+
+[Cache]
+MaxCacheSize=51200
+";
+
+    #[test]
+    fn untouched_file_round_trips() {
+        assert_eq!(KConfig::parse(KIOSLAVERC).to_string(), KIOSLAVERC);
+    }
+
+    #[test]
+    fn get_reads_group_keys() {
+        let config = KConfig::parse(KIOSLAVERC);
+        assert_eq!(config.get("Proxy Settings", "ProxyType").unwrap(), "1");
+        assert_eq!(
+            config.get("Proxy Settings", "httpProxy").unwrap(),
+            "http://127.0.0.1 7890"
+        );
+        assert_eq!(config.get("Cache", "MaxCacheSize").unwrap(), "51200");
+        // Only the German value is set.
+        assert_eq!(config.get("Proxy Settings", "Proxy Config Script"), None);
+        assert_eq!(config.get("Proxy Settings", "MaxCacheSize"), None);
+    }
+
+    #[test]
+    fn expands_marked_entries() {
+        assert_eq!(expand_env("a$$b"), "a$b");
+        assert_eq!(expand_env("$(hostname)"), "$(hostname)");
+        assert_eq!(expand_env("${SYSPROXY_KCONFIG_UNSET}x"), "x");
+        let home = env::var("HOME").unwrap_or_default();
+        let config = KConfig::parse("[G]\nk[$e]=$HOME/x\nplain=$HOME\n");
+        assert_eq!(config.get("G", "k").unwrap(), format!("{home}/x"));
+        assert_eq!(config.get("G", "plain").unwrap(), "$HOME");
+    }
+
+    #[test]
+    fn set_keeps_the_rest_of_the_file() {
+        let mut config = KConfig::parse(&KIOSLAVERC.replace("[$i]\n", ""));
+        config.set("Proxy Settings", "ProxyType", "2").unwrap();
+        config
+            .set("Proxy Settings", "socksProxy", "socks://127.0.0.1 7891")
+            .unwrap();
+        config.set("New Group", "key", " padded ").unwrap();
+        config.delete("Proxy Settings", "httpProxy").unwrap();
+
+        assert_eq!(
+            config.to_string(),
+            "\
+# written by hand
+[Proxy Settings]
+ProxyType=2
+NoProxyFor[$e]=localhost,$SYSPROXY_KCONFIG_TEST_HOST
+Proxy Config Script[de]=http://example.de/pac
+socksProxy=socks://127.0.0.1 7891
+
+[Cache]
+MaxCacheSize=51200
+
+[New Group]
+key=\\spadded\\s
+"
+        );
+        let config = KConfig::parse(&config.to_string());
+        assert_eq!(config.get("New Group", "key").unwrap(), " padded ");
+    }
+
+    #[test]
+    fn escaping_round_trips() {
+        for value in ["a\\b", "line\nbreak", "tab\there", " x ", "", "\u{1}"] {
+            assert_eq!(unescape(&escape(value)), value, "{value:?}");
+        }
+        assert_eq!(unescape("a\\;b"), "a\\;b");
+    }
+
+    fn assert_immutable(text: &str, key: &str) {
+        let mut config = KConfig::parse(text);
+        let immutable = |result: Result<()>| matches!(result, Err(Error::Immutable { .. }));
+        assert!(immutable(config.set("Proxy Settings", key, "1")), "{text}");
+        assert!(immutable(config.delete("Proxy Settings", key)), "{text}");
+        assert_eq!(config.to_string(), text);
+    }
+
+    #[test]
+    fn immutable_entries_are_refused() {
+        let text = "[Proxy Settings]\nProxyType[$i]=0\nhttpProxy=x\n";
+        assert_immutable(text, "ProxyType");
+
+        let mut config = KConfig::parse(text);
+        config.set("Proxy Settings", "httpProxy", "y").unwrap();
+        config.delete("Proxy Settings", "httpProxy").unwrap();
+    }
+
+    #[test]
+    fn immutable_groups_are_refused() {
+        let text = "[Proxy Settings][$i]\nProxyType=0\n\n[Cache]\nMaxCacheSize=1\n";
+        assert_immutable(text, "ProxyType");
+        assert_immutable(text, "httpProxy");
+
+        let mut config = KConfig::parse(text);
+        config.set("Cache", "MaxCacheSize", "2").unwrap();
+    }
+
+    #[test]
+    fn immutable_files_are_refused() {
+        assert_immutable(KIOSLAVERC, "ProxyType");
+        assert_immutable("[$i]\n[Cache]\nMaxCacheSize=1\n", "ProxyType");
+    }
+
+    #[test]
+    fn save_replaces_the_file() {
+        let dir = TempDir::new("kconfig");
+        let path = dir.join("kioslaverc");
+
+        let mut config = KConfig::load(&path).unwrap();
+        config.set("Proxy Settings", "ProxyType", "1").unwrap();
+        config.save(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[Proxy Settings]\nProxyType=1\n"
+        );
+        assert_eq!(fs::read_dir(&*dir).unwrap().count(), 1);
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{Autoproxy, Error, Result, Sysproxy};
+use crate::plan::{ProxyOp, planned};
+use crate::snapshot::Transaction;
+use crate::{
+    Autoproxy, BypassList, BypassRule, Error, ProxyBackend, ProxyChange, ProxyDiff, ProxyEndpoint,
+    ProxyEndpoints, ProxyMode, ProxyScheme, Result, SnapshotEntry, Sysproxy,
+};
 use log::debug;
 use std::{
     borrow::Cow,
@@ -32,6 +37,7 @@ enum ProxyType {
     Http,
     Https,
     Socks,
+    Ftp,
 }
 
 impl ProxyType {
@@ -41,6 +47,7 @@ impl ProxyType {
             Self::Http => "HTTPEnable",
             Self::Https => "HTTPSEnable",
             Self::Socks => "SOCKSEnable",
+            Self::Ftp => "FTPEnable",
         }
     }
     #[inline]
@@ -49,6 +56,7 @@ impl ProxyType {
             Self::Http => "HTTPProxy",
             Self::Https => "HTTPSProxy",
             Self::Socks => "SOCKSProxy",
+            Self::Ftp => "FTPProxy",
         }
     }
     #[inline]
@@ -57,6 +65,7 @@ impl ProxyType {
             Self::Http => "HTTPPort",
             Self::Https => "HTTPSPort",
             Self::Socks => "SOCKSPort",
+            Self::Ftp => "FTPPort",
         }
     }
 }
@@ -68,6 +77,7 @@ impl ProxyType {
             Self::Http => "-setwebproxy",
             Self::Https => "-setsecurewebproxy",
             Self::Socks => "-setsocksfirewallproxy",
+            Self::Ftp => "-setftpproxy",
         }
     }
     #[inline]
@@ -76,10 +86,52 @@ impl ProxyType {
             Self::Http => "-setwebproxystate",
             Self::Https => "-setsecurewebproxystate",
             Self::Socks => "-setsocksfirewallproxystate",
+            Self::Ftp => "-setftpproxystate",
         }
     }
 }
 
+impl ProxyType {
+    #[inline]
+    const fn scheme(&self) -> ProxyScheme {
+        match self {
+            Self::Http => ProxyScheme::Http,
+            Self::Https => ProxyScheme::Https,
+            Self::Socks => ProxyScheme::Socks,
+            Self::Ftp => ProxyScheme::Ftp,
+        }
+    }
+}
+
+/// Backend for the active network service. The inherent methods use the same settings.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MacosBackend;
+
+impl ProxyBackend for MacosBackend {
+    fn get_system_proxy(&self) -> Result<Sysproxy> {
+        Sysproxy::get_system_proxy()
+    }
+
+    fn set_system_proxy(&self, proxy: &Sysproxy) -> Result<()> {
+        proxy.set_system_proxy()
+    }
+
+    /// Credentials are never read back, so they are left out.
+    fn system_proxy_diff(&self, current: &Sysproxy, proxy: &Sysproxy) -> ProxyDiff {
+        current
+            .diff(proxy)
+            .retain(|change| *change != ProxyChange::Auth)
+    }
+
+    fn get_auto_proxy(&self) -> Result<Autoproxy> {
+        Autoproxy::get_auto_proxy()
+    }
+
+    fn set_auto_proxy(&self, autoproxy: &Autoproxy) -> Result<()> {
+        autoproxy.set_auto_proxy()
+    }
+}
+
 impl Sysproxy {
     #[inline]
     pub fn get_system_proxy() -> Result<Sysproxy> {
@@ -87,7 +139,7 @@ impl Sysproxy {
         let scp = SCPreferences::default(&CFString::new("sysproxy-rs"));
         let proxies_dict = get_proxies_by_service_uuid(&scp, &service_uuid)?;
 
-        let mut socks = parse_proxies_from_dict(&proxies_dict, ProxyType::Socks)?;
+        let socks = parse_proxies_from_dict(&proxies_dict, ProxyType::Socks)?;
         debug!("Getting SOCKS proxy: {:?}", socks);
 
         let http = parse_proxies_from_dict(&proxies_dict, ProxyType::Http)?;
@@ -96,48 +148,60 @@ impl Sysproxy {
         let https = parse_proxies_from_dict(&proxies_dict, ProxyType::Https)?;
         debug!("Getting HTTPS proxy: {:?}", https);
 
-        let bypass = parse_bypass_from_dict(&proxies_dict)?.join(",");
-        debug!("Getting bypass domains: {:?}", bypass);
+        let ftp = parse_proxies_from_dict(&proxies_dict, ProxyType::Ftp)?;
+        debug!("Getting FTP proxy: {:?}", ftp);
 
-        socks.bypass = bypass;
+        let bypass = parse_bypass_from_dict(&proxies_dict)?;
+        let bypass = BypassList::from_entries(bypass.iter().map(String::as_str));
+        debug!("Getting bypass domains: {:?}", bypass);
 
-        if !socks.enable {
-            if http.enable {
-                socks.enable = true;
-                socks.host = http.host;
-                socks.port = http.port;
-            }
+        // Nothing enabled: keep the SOCKS values so a disabled proxy still reports its endpoint.
+        if !(socks.enable || http.enable || https.enable || ftp.enable) {
+            return Ok(Sysproxy {
+                host: socks.host,
+                bypass,
+                port: socks.port,
+                enable: false,
+                endpoints: ProxyEndpoints::default(),
+                auth: None,
+            });
+        }
 
-            if https.enable {
-                socks.enable = true;
-                socks.host = https.host;
-                socks.port = https.port;
+        let mut endpoints = ProxyEndpoints::default();
+        for (scheme, proxy) in [
+            (ProxyScheme::Socks, socks),
+            (ProxyScheme::Http, http),
+            (ProxyScheme::Https, https),
+            (ProxyScheme::Ftp, ftp),
+        ] {
+            if proxy.enable {
+                endpoints.set(scheme, Some(ProxyEndpoint::new(proxy.host, proxy.port)));
             }
         }
 
-        Ok(socks)
+        Ok(Sysproxy::from_endpoints(true, endpoints, bypass))
     }
 
     #[inline]
     pub fn set_system_proxy(&self) -> Result<()> {
+        self.validate()?;
+
         let service = get_active_network_service()?;
         let service = service.to_string();
         let service = service.as_str();
 
         debug!("Use network service: {}", service);
 
-        debug!("Setting SOCKS proxy");
-        self.set_socks(service)?;
-
-        debug!("Setting HTTPS proxy");
-        self.set_https(service)?;
-
-        debug!("Setting HTTP proxy");
-        self.set_http(service)?;
-
-        debug!("Setting bypass domains");
-        self.set_bypass(service)?;
-        Ok(())
+        // Capture first, so a failed networksetup call puts the previous settings back.
+        let snapshot = capture_snapshot()?;
+        // FTP is written too, so an earlier FTP proxy is turned off with the others.
+        Transaction::new()
+            .step("SOCKS proxy", || self.set_socks(service))
+            .step("HTTPS proxy", || self.set_https(service))
+            .step("HTTP proxy", || self.set_http(service))
+            .step("FTP proxy", || self.set_ftp(service))
+            .step("bypass domains", || self.set_bypass(service))
+            .commit(|| restore_snapshot(&snapshot))
     }
 
     #[inline]
@@ -176,17 +240,31 @@ impl Sysproxy {
         parse_proxies_from_dict(cfd, ProxyType::Socks)
     }
 
+    #[inline]
+    pub fn get_ftp(
+        service: &CFString,
+        cfd: Option<&CFDictionary<CFString, CFType>>,
+    ) -> Result<Sysproxy> {
+        let cfd = match cfd {
+            Some(s) => s,
+            None => &get_proxies_dict_from_service_uuid(service)?,
+        };
+        parse_proxies_from_dict(cfd, ProxyType::Ftp)
+    }
+
     #[inline]
     pub fn get_bypass(
         service: &CFString,
         cfd: Option<&CFDictionary<CFString, CFType>>,
-    ) -> Result<String> {
+    ) -> Result<BypassList> {
         let cfd = match cfd {
             Some(s) => s,
             None => &get_proxies_dict_from_service_uuid(service)?,
         };
         let bypass_list = parse_bypass_from_dict(cfd)?;
-        Ok(bypass_list.join(","))
+        Ok(BypassList::from_entries(
+            bypass_list.iter().map(String::as_str),
+        ))
     }
 
     #[inline]
@@ -204,6 +282,11 @@ impl Sysproxy {
         set_proxy(self, ProxyType::Socks, service)
     }
 
+    #[inline]
+    pub fn set_ftp(&self, service: &str) -> Result<()> {
+        set_proxy(self, ProxyType::Ftp, service)
+    }
+
     #[inline]
     pub fn set_bypass(&self, service: &str) -> Result<()> {
         set_bypass(self, service)
@@ -252,8 +335,210 @@ impl Autoproxy {
     }
 }
 
+impl ProxyMode {
+    #[inline]
+    pub fn get_proxy_mode() -> Result<ProxyMode> {
+        let auto = Autoproxy::get_auto_proxy()?;
+        if auto.enable {
+            return Ok(ProxyMode::Auto(auto.url));
+        }
+
+        let service_uuid = get_active_network_service_uuid()?;
+        let scp = SCPreferences::default(&CFString::new("sysproxy-rs"));
+        let proxies_dict = get_proxies_by_service_uuid(&scp, &service_uuid)?;
+        if read_bool_flag(&proxies_dict, "ProxyAutoDiscoveryEnable") {
+            return Ok(ProxyMode::AutoDetect);
+        }
+
+        let proxy = Sysproxy::get_system_proxy()?;
+        match proxy.enable {
+            true => Ok(ProxyMode::Manual(Box::new(proxy))),
+            false => Ok(ProxyMode::Direct),
+        }
+    }
+
+    #[inline]
+    pub fn set_proxy_mode(&self) -> Result<()> {
+        let service = get_active_network_service()?.to_string();
+        let service = service.as_str();
+
+        // macOS keeps every mode switch separately, turn off the ones not selected.
+        if let ProxyMode::Manual(proxy) = self {
+            Sysproxy {
+                enable: true,
+                ..(**proxy).clone()
+            }
+            .set_system_proxy()?;
+        } else {
+            for proxy_type in [
+                ProxyType::Socks,
+                ProxyType::Https,
+                ProxyType::Http,
+                ProxyType::Ftp,
+            ] {
+                run_networksetup(&[proxy_type.as_state_cmd(), service, "off"])?;
+            }
+        }
+
+        if let ProxyMode::Auto(url) = self {
+            run_networksetup(&["-setautoproxyurl", service, url])?;
+        }
+        let auto = matches!(self, ProxyMode::Auto(_));
+        let discovery = matches!(self, ProxyMode::AutoDetect);
+        run_networksetup(&[
+            "-setautoproxystate",
+            service,
+            if auto { "on" } else { "off" },
+        ])?;
+        run_networksetup(&[
+            "-setproxyautodiscovery",
+            service,
+            if discovery { "on" } else { "off" },
+        ])?;
+
+        Ok(())
+    }
+}
+
+const SNAPSHOT_TYPES: [ProxyType; 4] = [
+    ProxyType::Socks,
+    ProxyType::Https,
+    ProxyType::Http,
+    ProxyType::Ftp,
+];
+
+/// Proxy keys of the active network service, numbers and strings in their text form and
+/// `ExceptionsList` joined with commas.
+pub(crate) fn capture_snapshot() -> Result<Vec<SnapshotEntry>> {
+    let service = get_active_network_service()?.to_string();
+    let service_uuid = get_active_network_service_uuid()?;
+    let scp = SCPreferences::default(&CFString::new("sysproxy-rs"));
+    let proxies_dict = get_proxies_by_service_uuid(&scp, &service_uuid)?;
+
+    let number = |key: &'static str| {
+        get_proxy_value(&proxies_dict, key)
+            .and_then(|x| x.downcast::<CFNumber>())
+            .and_then(|num| num.to_i32())
+            .map(|v| v.to_string())
+    };
+    let string = |key: &'static str| {
+        get_proxy_value(&proxies_dict, key)
+            .and_then(|x| x.downcast::<CFString>().map(|s| s.to_string()))
+    };
+
+    let mut entries = Vec::new();
+    for proxy_type in SNAPSHOT_TYPES {
+        entries.push(SnapshotEntry::new(
+            service.as_str(),
+            proxy_type.as_host(),
+            string(proxy_type.as_host()),
+        ));
+        entries.push(SnapshotEntry::new(
+            service.as_str(),
+            proxy_type.as_port(),
+            number(proxy_type.as_port()),
+        ));
+        entries.push(SnapshotEntry::new(
+            service.as_str(),
+            proxy_type.as_enable(),
+            number(proxy_type.as_enable()),
+        ));
+    }
+    let bypass = get_proxy_value(&proxies_dict, "ExceptionsList")
+        .is_some()
+        .then(|| parse_bypass_from_dict(&proxies_dict).map(|list| list.join(",")))
+        .transpose()?;
+    entries.push(SnapshotEntry::new(
+        service.as_str(),
+        "ExceptionsList",
+        bypass,
+    ));
+    entries.push(SnapshotEntry::new(
+        service.as_str(),
+        "ProxyAutoConfigURLString",
+        string("ProxyAutoConfigURLString"),
+    ));
+    entries.push(SnapshotEntry::new(
+        service.as_str(),
+        "ProxyAutoConfigEnable",
+        number("ProxyAutoConfigEnable"),
+    ));
+    entries.push(SnapshotEntry::new(
+        service.as_str(),
+        "ProxyAutoDiscoveryEnable",
+        number("ProxyAutoDiscoveryEnable"),
+    ));
+    Ok(entries)
+}
+
+/// Write the captured keys back through networksetup, one network service at a time.
+pub(crate) fn restore_snapshot(entries: &[SnapshotEntry]) -> Result<()> {
+    let mut services = Vec::<&str>::new();
+    for entry in entries {
+        if !services.contains(&entry.scope.as_str()) {
+            services.push(&entry.scope);
+        }
+    }
+
+    for service in services {
+        let value_of = |key: &str| {
+            entries
+                .iter()
+                .find(|entry| entry.scope == service && entry.key == key)
+                .and_then(|entry| entry.value.as_deref())
+        };
+        let state = |key: &str| match value_of(key) {
+            Some(value) if value != "0" => "on",
+            _ => "off",
+        };
+
+        for proxy_type in SNAPSHOT_TYPES {
+            if let (Some(host), Some(port)) = (
+                value_of(proxy_type.as_host()),
+                value_of(proxy_type.as_port()),
+            ) {
+                if !host.is_empty() {
+                    run_networksetup(&[proxy_type.as_set_str(), service, host, port])?;
+                }
+            }
+            run_networksetup(&[
+                proxy_type.as_state_cmd(),
+                service,
+                state(proxy_type.as_enable()),
+            ])?;
+        }
+
+        let mut args = vec!["-setproxybypassdomains", service];
+        match value_of("ExceptionsList") {
+            Some(bypass) if !bypass.is_empty() => args.extend(bypass.split(',')),
+            _ => args.push("Empty"),
+        }
+        run_networksetup(&args)?;
+
+        let url = match value_of("ProxyAutoConfigURLString") {
+            Some(url) if !url.is_empty() => url,
+            _ => "\"\"",
+        };
+        run_networksetup(&["-setautoproxyurl", service, url])?;
+        run_networksetup(&[
+            "-setautoproxystate",
+            service,
+            state("ProxyAutoConfigEnable"),
+        ])?;
+        run_networksetup(&[
+            "-setproxyautodiscovery",
+            service,
+            state("ProxyAutoDiscoveryEnable"),
+        ])?;
+    }
+    Ok(())
+}
+
 #[inline]
 fn run_networksetup<'a>(args: &[&str]) -> Result<Cow<'a, str>> {
+    if planned(|| ProxyOp::new("networksetup", args.iter().copied())) {
+        return Ok(Cow::Borrowed(""));
+    }
     let output = Command::new("networksetup")
         .args(args)
         .stdout(Stdio::piped())
@@ -301,13 +586,24 @@ fn parse_networksetup_output<'a>(args: &[&str], output: Output) -> Result<Cow<'a
 
 #[inline]
 fn set_proxy(proxy: &Sysproxy, proxy_type: ProxyType, service: &str) -> Result<()> {
-    let host = proxy.host.as_str();
-    let port = format!("{}", proxy.port);
-    let port = port.as_str();
-
-    run_networksetup(&[proxy_type.as_set_str(), service, host, port])?;
+    let endpoint = proxy.endpoint(proxy_type.scheme());
+
+    // An unset endpoint only turns the scheme off, the stored host/port is left as is.
+    if let Some(endpoint) = &endpoint {
+        let port = format!("{}", endpoint.port);
+        run_networksetup(&[
+            proxy_type.as_set_str(),
+            service,
+            endpoint.host.as_str(),
+            port.as_str(),
+        ])?;
+    }
 
-    let enable = if proxy.enable { "on" } else { "off" };
+    let enable = if proxy.enable && endpoint.is_some() {
+        "on"
+    } else {
+        "off"
+    };
 
     run_networksetup(&[proxy_type.as_state_cmd(), service, enable])?;
 
@@ -317,12 +613,14 @@ fn set_proxy(proxy: &Sysproxy, proxy_type: ProxyType, service: &str) -> Result<(
 #[inline]
 fn set_bypass(proxy: &Sysproxy, service: &str) -> Result<()> {
     let mut args = vec!["-setproxybypassdomains", service];
-    let domains: Vec<&str> = if proxy.bypass.is_empty() {
-        Vec::new()
-    } else {
-        proxy.bypass.split(",").collect()
-    };
-    args.extend(&domains);
+    // "Exclude simple hostnames" is not reachable through networksetup, so `<local>` is skipped.
+    let domains = proxy
+        .bypass
+        .iter()
+        .filter(|rule| **rule != BypassRule::LocalSimpleNames)
+        .map(|rule| rule.to_string())
+        .collect::<Vec<String>>();
+    args.extend(domains.iter().map(String::as_str));
     run_networksetup(&args)?;
     Ok(())
 }
@@ -383,7 +681,9 @@ fn parse_proxies_from_dict(
         enable,
         host,
         port,
-        bypass: String::new(),
+        bypass: BypassList::new(),
+        endpoints: ProxyEndpoints::default(),
+        auth: None,
     })
 }
 
@@ -584,7 +884,8 @@ fn test_set_bypass() {
         host: "proxy.example.com".into(),
         port: 8080,
         enable: true,
-        bypass: "no".into(),
+        bypass: BypassList::from(vec![BypassRule::Host("no".into())]),
+        ..Default::default()
     };
     let result = proxy.set_bypass("Wi-Fi");
     if let Err(e) = result {
@@ -1,7 +1,7 @@
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
 
-use crate::{Autoproxy, Sysproxy};
+use crate::{Autoproxy, BypassList, Sysproxy};
 
 #[napi(object)]
 pub struct JsSysproxy {
@@ -17,25 +17,37 @@ pub struct JsAutoproxy {
     pub enable: bool,
 }
 
+/// JS numbers are wider than a port, reject instead of truncating.
+#[inline]
+fn to_port(port: u32) -> crate::Result<u16> {
+    u16::try_from(port).map_err(|_| crate::Error::InvalidConfig {
+        field: "port".into(),
+        reason: "greater than 65535",
+    })
+}
+
 impl From<Sysproxy> for JsSysproxy {
     fn from(p: Sysproxy) -> Self {
         Self {
             host: p.host,
             port: p.port as u32,
-            bypass: p.bypass,
+            bypass: p.bypass.to_string(),
             enable: p.enable,
         }
     }
 }
 
-impl From<JsSysproxy> for Sysproxy {
-    fn from(p: JsSysproxy) -> Self {
-        Self {
+impl TryFrom<JsSysproxy> for Sysproxy {
+    type Error = crate::Error;
+
+    fn try_from(p: JsSysproxy) -> crate::Result<Self> {
+        Ok(Self {
             host: p.host,
-            port: p.port as u16,
-            bypass: p.bypass,
+            port: to_port(p.port)?,
+            bypass: BypassList::parse(&p.bypass)?,
             enable: p.enable,
-        }
+            ..Default::default()
+        })
     }
 }
 
@@ -66,7 +78,7 @@ pub fn get_system_proxy() -> Result<JsSysproxy> {
 
 #[napi]
 pub fn set_system_proxy(proxy: JsSysproxy) -> Result<()> {
-    let p: Sysproxy = proxy.into();
+    let p = Sysproxy::try_from(proxy).map_err(|e| Error::from_reason(e.to_string()))?;
     p.set_system_proxy()
         .map_err(|e| Error::from_reason(e.to_string()))
 }
@@ -88,11 +100,14 @@ pub fn set_auto_proxy(proxy: JsAutoproxy) -> Result<()> {
 // Compatibility API for clash-party
 #[napi]
 pub fn trigger_manual_proxy(enable: bool, host: String, port: u32, bypass: String) -> Result<()> {
+    let bypass = BypassList::parse(&bypass).map_err(|e| Error::from_reason(e.to_string()))?;
+    let port = to_port(port).map_err(|e| Error::from_reason(e.to_string()))?;
     let p = Sysproxy {
         enable,
         host,
-        port: port as u16,
+        port,
         bypass,
+        ..Default::default()
     };
     p.set_system_proxy()
         .map_err(|e| Error::from_reason(e.to_string()))
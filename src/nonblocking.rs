@@ -0,0 +1,143 @@
+//! Async getters and setters, behind the `async` feature.
+//!
+//! On Linux the commands run through `tokio::process`, and dropping a future kills the
+//! commands it started, so the calls can be cancelled or given a deadline with
+//! [`with_timeout`]. Elsewhere the blocking calls run on tokio's blocking pool, where they
+//! finish even if the future is dropped.
+
+use crate::{Autoproxy, Error, Result, Sysproxy};
+use std::{future::Future, time::Duration};
+
+/// Run `future`, or fail with [`Error::Timeout`] once `duration` has passed.
+///
+/// Example:
+/// ```no_run
+/// # async fn run() -> sysproxy::Result<()> {
+/// use std::time::Duration;
+/// use sysproxy::{Sysproxy, with_timeout};
+///
+/// let proxy = with_timeout(Duration::from_secs(5), Sysproxy::get_system_proxy_async()).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn with_timeout<T>(
+    duration: Duration,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::time::timeout(duration, future)
+        .await
+        .map_err(|_| Error::Timeout(duration))?
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn spawn_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?
+}
+
+#[cfg(target_os = "linux")]
+impl Sysproxy {
+    #[inline]
+    pub async fn get_system_proxy_async() -> Result<Sysproxy> {
+        crate::linux::get_system_proxy_async().await
+    }
+
+    /// Same writes as [`Sysproxy::set_system_proxy`], rolled back if one fails.
+    #[inline]
+    pub async fn set_system_proxy_async(&self) -> Result<()> {
+        crate::linux::set_system_proxy_async(self).await
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Autoproxy {
+    #[inline]
+    pub async fn get_auto_proxy_async() -> Result<Autoproxy> {
+        crate::linux::get_auto_proxy_async().await
+    }
+
+    /// Same writes as [`Autoproxy::set_auto_proxy`], rolled back if one fails.
+    #[inline]
+    pub async fn set_auto_proxy_async(&self) -> Result<()> {
+        crate::linux::set_auto_proxy_async(self).await
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+impl Sysproxy {
+    #[inline]
+    pub async fn get_system_proxy_async() -> Result<Sysproxy> {
+        spawn_blocking(Sysproxy::get_system_proxy).await
+    }
+
+    #[inline]
+    pub async fn set_system_proxy_async(&self) -> Result<()> {
+        let proxy = self.clone();
+        spawn_blocking(move || proxy.set_system_proxy()).await
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+impl Autoproxy {
+    #[inline]
+    pub async fn get_auto_proxy_async() -> Result<Autoproxy> {
+        spawn_blocking(Autoproxy::get_auto_proxy).await
+    }
+
+    #[inline]
+    pub async fn set_auto_proxy_async(&self) -> Result<()> {
+        let autoproxy = self.clone();
+        spawn_blocking(move || autoproxy.set_auto_proxy()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[tokio::test]
+    async fn with_timeout_gives_up() {
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        };
+        assert!(matches!(
+            with_timeout(Duration::from_millis(10), slow).await,
+            Err(Error::Timeout(_))
+        ));
+        assert_eq!(
+            with_timeout(Duration::from_secs(5), async { Ok(1) })
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn timed_out_command_is_killed() {
+        let dir = crate::utils::tests::TempDir::new("kill");
+        let pid_file = dir.join("pid");
+
+        let mut command = tokio::process::Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("echo $$ > '{}'; exec sleep 5", pid_file.display()));
+        let result =
+            with_timeout(Duration::from_millis(200), crate::utils::run_async(command)).await;
+        assert!(matches!(result, Err(Error::Timeout(_))));
+
+        // Killed children are gone, or zombies until tokio reaps them.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        if let Ok(stat) = stat {
+            let state = stat.rsplit_once(") ").unwrap().1;
+            assert!(state.starts_with('Z'), "still running: {stat}");
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! Proxy settings for the system package managers.
+//!
+//! apt, dnf and pacman run as root and read their own configuration, not the desktop proxy.
+//! [`PackageProxy`] writes the proxy there, below a root directory so an image or a test
+//! directory can be configured as well as the running system.
+
+use crate::{
+    BypassRule, Error, ProxyOp, ProxyScheme, Result, Sysproxy,
+    devtools::{ConfigFile, Syntax, proxy_url},
+    plan::planned,
+    snapshot::Transaction,
+    utils::{read_file, remove_file, restore_file, write_file, write_private_file},
+};
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// apt reads every file in `apt.conf.d`, so this one is ours alone.
+const APT_FILE: &str = "etc/apt/apt.conf.d/95sysproxy";
+const DNF_FILE: &str = "etc/dnf/dnf.conf";
+const PACMAN_FILE: &str = "etc/pacman.conf";
+
+const APT_HEADER: &str = "// Managed by sysproxy, removed when the proxy is disabled.\n";
+
+/// The group apt downloads as, which may read the apt file when it holds credentials.
+const APT_GROUP: &str = "_apt";
+
+/// How our pacman download command starts and ends. Only a command in exactly this form is
+/// taken for ours, so a curl command of the user's own is not.
+const PACMAN_CURL: &str = "/usr/bin/curl --silent --show-error --location --fail --continue-at -";
+const PACMAN_OUTPUT: &str = " --output %o %u";
+
+/// A package manager with a proxy setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum PackageManager {
+    /// `Acquire::http::Proxy` and `Acquire::https::Proxy` in `apt.conf.d/95sysproxy`, with
+    /// a `DIRECT` entry for every bypassed host. With credentials the file is only readable
+    /// by root and the `_apt` group.
+    Apt,
+    /// `proxy` in the `[main]` section of `dnf.conf`. dnf has no bypass list.
+    Dnf,
+    /// An `XferCommand` in `pacman.conf` that downloads with curl through the proxy, since
+    /// pacman runs under sudo without the proxy variables. A download command of the user's
+    /// own is never replaced.
+    Pacman,
+}
+
+impl PackageManager {
+    pub const ALL: [PackageManager; 3] = [
+        PackageManager::Apt,
+        PackageManager::Dnf,
+        PackageManager::Pacman,
+    ];
+
+    #[inline]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            PackageManager::Apt => "apt",
+            PackageManager::Dnf => "dnf",
+            PackageManager::Pacman => "pacman",
+        }
+    }
+
+    #[inline]
+    const fn file(&self) -> &'static str {
+        match self {
+            PackageManager::Apt => APT_FILE,
+            PackageManager::Dnf => DNF_FILE,
+            PackageManager::Pacman => PACMAN_FILE,
+        }
+    }
+
+    /// Whether the package manager is installed under `root`.
+    fn is_installed(&self, root: &Path) -> bool {
+        match self {
+            PackageManager::Apt => root.join("etc/apt").is_dir(),
+            PackageManager::Dnf => root.join("etc/dnf").is_dir(),
+            PackageManager::Pacman => root.join(PACMAN_FILE).is_file(),
+        }
+    }
+
+    /// The config file with `proxy` applied to `text`, or removed when `proxy` is `None`.
+    /// `None` deletes the file.
+    fn update(&self, text: Option<&str>, proxy: Option<&Sysproxy>) -> Result<Option<String>> {
+        // dnf.conf and pacman.conf have to stay readable for every user's queries.
+        if *self != PackageManager::Apt && proxy.is_some_and(|proxy| proxy.auth.is_some()) {
+            return Err(Error::InvalidConfig {
+                field: "auth".into(),
+                reason: "dnf.conf and pacman.conf are readable by every user",
+            });
+        }
+        Ok(match self {
+            PackageManager::Apt => proxy.map(format_apt),
+            PackageManager::Dnf => {
+                let mut config = ConfigFile::parse(text.unwrap_or_default(), Syntax::Ini);
+                let url = proxy.and_then(single_url);
+                config.set(Some("main"), "proxy", url.as_deref());
+                Some(config.to_string()).filter(|text| !text.is_empty())
+            }
+            PackageManager::Pacman => {
+                let mut config = ConfigFile::parse(text.unwrap_or_default(), Syntax::Ini);
+                let current = config.get(Some("options"), "XferCommand");
+                let ours = current.as_deref().is_some_and(|command| {
+                    command.starts_with(PACMAN_CURL) && command.ends_with(PACMAN_OUTPUT)
+                });
+                match (proxy, current) {
+                    // Ours would have no way back to it.
+                    (Some(_), Some(_)) if !ours => {
+                        return Err(Error::InvalidConfig {
+                            field: "XferCommand".into(),
+                            reason: "pacman.conf already sets a download command of its own",
+                        });
+                    }
+                    (Some(proxy), _) => {
+                        config.set(Some("options"), "XferCommand", Some(&pacman_command(proxy)));
+                    }
+                    // Somebody else's download command stays.
+                    (None, Some(_)) if ours => config.set(Some("options"), "XferCommand", None),
+                    (None, _) => {}
+                }
+                Some(config.to_string()).filter(|text| !text.is_empty())
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for PackageManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One proxy for every repository: the http endpoint, then https, then socks.
+#[inline]
+fn single_url(proxy: &Sysproxy) -> Option<String> {
+    proxy_url(proxy, ProxyScheme::Http).or_else(|| proxy_url(proxy, ProxyScheme::Https))
+}
+
+fn format_apt(proxy: &Sysproxy) -> String {
+    let mut text = APT_HEADER.to_string();
+    for (scheme, name) in [(ProxyScheme::Http, "http"), (ProxyScheme::Https, "https")] {
+        if let Some(url) = proxy_url(proxy, scheme) {
+            text.push_str(&format!("Acquire::{name}::Proxy \"{url}\";\n"));
+        }
+    }
+    // apt only exempts exact host names.
+    for rule in &proxy.bypass {
+        let BypassRule::Host(host) = rule else {
+            log::debug!("apt has no equivalent of {rule}, skipping it");
+            continue;
+        };
+        for name in ["http", "https"] {
+            text.push_str(&format!("Acquire::{name}::Proxy::{host} \"DIRECT\";\n"));
+        }
+    }
+    text
+}
+
+/// pacman's own downloader flags, with curl doing the transfer through the proxy.
+fn pacman_command(proxy: &Sysproxy) -> String {
+    let mut command = String::from(PACMAN_CURL);
+    if let Some(url) = single_url(proxy) {
+        command.push_str(&format!(" --proxy {url}"));
+    }
+    let no_proxy = proxy.bypass.to_no_proxy();
+    if !no_proxy.is_empty() {
+        command.push_str(&format!(" --noproxy {no_proxy}"));
+    }
+    command.push_str(PACMAN_OUTPUT);
+    command
+}
+
+/// Writes the proxy to the apt, dnf and pacman configuration below a root directory.
+///
+/// Only the installed package managers are written unless [`PackageProxy::managers`]
+/// picks them. The apt file is ours and removed again; in dnf.conf and pacman.conf only the
+/// proxy keys are changed, everything else is kept. Writing to `/` needs root.
+///
+/// Example:
+/// ```no_run
+/// use sysproxy::{PackageProxy, Sysproxy};
+///
+/// let proxy = Sysproxy::builder().host("10.0.0.1").port(3128).build()?;
+/// PackageProxy::new().apply(&proxy)?;
+/// # Ok::<(), sysproxy::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageProxy {
+    root: PathBuf,
+    managers: Option<Vec<PackageManager>>,
+}
+
+impl Default for PackageProxy {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PackageProxy {
+    /// The running system.
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_root("/")
+    }
+
+    /// The system installed below `root`.
+    #[inline]
+    pub fn with_root(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            managers: None,
+        }
+    }
+
+    /// Write these package managers, installed or not.
+    #[inline]
+    pub fn managers(mut self, managers: impl IntoIterator<Item = PackageManager>) -> Self {
+        self.managers = Some(managers.into_iter().collect());
+        self
+    }
+
+    /// The file `manager` is configured in.
+    #[inline]
+    pub fn config_path(&self, manager: PackageManager) -> PathBuf {
+        self.root.join(manager.file())
+    }
+
+    fn selected(&self) -> Vec<PackageManager> {
+        match &self.managers {
+            Some(managers) => managers.clone(),
+            None => PackageManager::ALL
+                .into_iter()
+                .filter(|manager| manager.is_installed(&self.root))
+                .collect(),
+        }
+    }
+
+    /// Set `proxy` for each selected package manager, or [`remove`](Self::remove) it when it
+    /// is disabled. dnf.conf and pacman.conf are shared and refuse credentials, apt gets a
+    /// file of its own.
+    pub fn apply(&self, proxy: &Sysproxy) -> Result<()> {
+        if !proxy.enable {
+            return self.remove();
+        }
+        proxy.validate()?;
+        self.update(Some(proxy))
+    }
+
+    /// Take the proxy out of every package manager.
+    #[inline]
+    pub fn remove(&self) -> Result<()> {
+        self.update(None)
+    }
+
+    fn update(&self, proxy: Option<&Sysproxy>) -> Result<()> {
+        let mut writes = Vec::new();
+        for manager in self.selected() {
+            let path = self.config_path(manager);
+            let previous = read_file(&path)?;
+            let text = manager.update(previous.as_deref(), proxy)?;
+            if text != previous {
+                writes.push((manager, path, previous, text));
+            }
+        }
+
+        let secret = proxy.is_some_and(|proxy| proxy.auth.is_some());
+        let mut transaction = Transaction::new();
+        for (manager, path, _, text) in &writes {
+            transaction = transaction.step(manager.as_str(), move || match text {
+                Some(text) if secret => {
+                    write_private_file(path, text)?;
+                    self.share_with_apt(path)
+                }
+                Some(text) => write_file(path, text),
+                None => remove_file(path),
+            });
+        }
+        transaction.commit(|| {
+            writes
+                .iter()
+                .try_for_each(|(_, path, previous, _)| restore_file(path, previous.as_deref()))
+        })
+    }
+
+    /// Let the `_apt` group read `path` as well, when the system has one and we may hand the
+    /// file to it. Otherwise it stays readable by its owner only.
+    fn share_with_apt(&self, path: &Path) -> Result<()> {
+        let Some(gid) = group_id(&self.root, APT_GROUP) else {
+            return Ok(());
+        };
+        if planned(|| ProxyOp::new("chgrp", [APT_GROUP.to_string(), path.display().to_string()])) {
+            return Ok(());
+        }
+        match std::os::unix::fs::chown(path, None, Some(gid)) {
+            Ok(()) => {
+                fs::set_permissions(path, fs::Permissions::from_mode(0o640))?;
+                Ok(())
+            }
+            Err(e) => {
+                log::debug!("Keeping {} private, chgrp failed: {e}", path.display());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The id of group `name` in `etc/group` under `root`.
+fn group_id(root: &Path, name: &str) -> Option<u32> {
+    let groups = fs::read_to_string(root.join("etc/group")).ok()?;
+    groups.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        (fields.next()? == name).then_some(())?;
+        fields.nth(1)?.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use crate::{ProxyAuth, utils::tests::TempDir};
+    use std::fs;
+
+    fn proxy() -> Sysproxy {
+        crate::utils::tests::proxy("localhost,*.corp,192.168.0.0/16")
+    }
+
+    #[test]
+    fn apt_gets_its_own_file() {
+        let root = TempDir::new("packages-apt");
+        fs::create_dir_all(root.join("etc/apt/apt.conf.d")).unwrap();
+        let packages = PackageProxy::with_root(&*root);
+        packages.apply(&proxy()).unwrap();
+
+        let path = packages.config_path(PackageManager::Apt);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "// Managed by sysproxy, removed when the proxy is disabled.\nAcquire::http::Proxy \"http://127.0.0.1:7890\";\nAcquire::https::Proxy \"http://127.0.0.1:7890\";\nAcquire::http::Proxy::localhost \"DIRECT\";\nAcquire::https::Proxy::localhost \"DIRECT\";\n"
+        );
+        // Only apt is installed here.
+        assert!(!packages.config_path(PackageManager::Dnf).exists());
+
+        packages.remove().unwrap();
+        assert!(!path.exists());
+
+        // Credentials keep the file private, there is no `_apt` group under this root.
+        let mut secret = proxy();
+        secret.auth = Some(ProxyAuth::new("user", "p@ss"));
+        packages.apply(&secret).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn dnf_keeps_the_rest_of_dnf_conf() {
+        let root = TempDir::new("packages-dnf");
+        let packages = PackageProxy::with_root(&*root).managers([PackageManager::Dnf]);
+        let path = packages.config_path(PackageManager::Dnf);
+        let original = "[main]\ngpgcheck=1\ninstallonly_limit=3\n\n[updates]\nenabled=1\n";
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, original).unwrap();
+
+        packages.apply(&proxy()).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[main]\ngpgcheck=1\ninstallonly_limit=3\nproxy = http://127.0.0.1:7890\n\n[updates]\nenabled=1\n"
+        );
+
+        // dnf.conf is readable by every user, so it gets no password.
+        let mut secret = proxy();
+        secret.auth = Some(ProxyAuth::new("user", "p@ss"));
+        assert!(matches!(
+            packages.apply(&secret),
+            Err(Error::InvalidConfig { field, .. }) if field == "auth"
+        ));
+        assert!(!fs::read_to_string(&path).unwrap().contains("p@ss"));
+
+        packages.apply(&Sysproxy::default()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+    }
+
+    #[test]
+    fn pacman_downloads_through_curl() {
+        let root = TempDir::new("packages-pacman");
+        let path = root.join(PACMAN_FILE);
+        let original = "[options]\nHoldPkg = pacman glibc\n#XferCommand = /usr/bin/wget %u\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n";
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, original).unwrap();
+
+        let packages = PackageProxy::with_root(&*root);
+        packages.apply(&proxy()).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[options]\nHoldPkg = pacman glibc\n#XferCommand = /usr/bin/wget %u\nXferCommand = /usr/bin/curl --silent --show-error --location --fail --continue-at - --proxy http://127.0.0.1:7890 --noproxy localhost,.corp,192.168.0.0/16 --output %o %u\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n"
+        );
+
+        packages.remove().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+        // A download command that isn't ours is neither replaced nor removed, even a curl
+        // command with a proxy of its own.
+        for command in [
+            "/usr/bin/aria2c %u",
+            "/usr/bin/curl --proxy http://corp:3128 -L -C - -f -o %o %u",
+        ] {
+            let custom = format!("[options]\nXferCommand = {command}\n");
+            fs::write(&path, &custom).unwrap();
+            assert!(matches!(
+                packages.apply(&proxy()),
+                Err(Error::InvalidConfig { field, .. }) if field == "XferCommand"
+            ));
+            packages.remove().unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), custom);
+        }
+    }
+}
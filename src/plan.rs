@@ -0,0 +1,277 @@
+//! Dry runs: record the writes a change would make instead of making them.
+
+use crate::{Autoproxy, ProxyMode, Result, Sysproxy, utils::redact_args};
+use std::{cell::RefCell, fmt, process::Command};
+
+thread_local! {
+    /// Set while [`record`] runs. Every backend write checks it before touching the system.
+    static RECORDER: RefCell<Option<Vec<ProxyOp>>> = const { RefCell::new(None) };
+}
+
+/// One write: a command line, or on Windows the WinINet call and its options.
+///
+/// Passwords in the arguments are redacted, so a plan can be shown or logged as is.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProxyOp {
+    pub program: String,
+    pub args: Vec<String>,
+    /// The arguments as run, when redacting changed them.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    raw_args: Option<Vec<String>>,
+}
+
+impl ProxyOp {
+    #[inline]
+    pub fn new<I, S>(program: impl Into<String>, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let raw_args = args.into_iter().map(Into::into).collect::<Vec<String>>();
+        let args = redact_args(&raw_args);
+        Self {
+            program: program.into(),
+            raw_args: (args != raw_args).then_some(raw_args),
+            args,
+        }
+    }
+
+    /// The arguments to run the op with, passwords included.
+    #[cfg(any(test, feature = "async"))]
+    #[inline]
+    pub(crate) fn raw_args(&self) -> &[String] {
+        self.raw_args.as_deref().unwrap_or(&self.args)
+    }
+}
+
+impl fmt::Debug for ProxyOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyOp")
+            .field("program", &self.program)
+            .field("args", &self.args)
+            .finish()
+    }
+}
+
+impl From<&Command> for ProxyOp {
+    fn from(command: &Command) -> Self {
+        Self::new(
+            command.get_program().to_string_lossy(),
+            command.get_args().map(|arg| arg.to_string_lossy()),
+        )
+    }
+}
+
+/// Quote an argument for a POSIX shell when it needs it.
+#[inline]
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c));
+    match plain {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', r"'\''")),
+    }
+}
+
+/// Shell-quoted, ready to paste into a terminal.
+impl fmt::Display for ProxyOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", shell_quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", shell_quote(arg))?;
+        }
+        Ok(())
+    }
+}
+
+/// The ordered writes a change would make, see [`Sysproxy::plan`].
+///
+/// Reads still happen while planning, since some writes depend on the current settings.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct ProxyPlan {
+    ops: Vec<ProxyOp>,
+}
+
+impl ProxyPlan {
+    #[inline]
+    pub fn ops(&self) -> &[ProxyOp] {
+        &self.ops
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// One operation per line.
+impl fmt::Display for ProxyPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for op in &self.ops {
+            writeln!(f, "{op}")?;
+        }
+        Ok(())
+    }
+}
+
+impl IntoIterator for ProxyPlan {
+    type Item = ProxyOp;
+    type IntoIter = std::vec::IntoIter<ProxyOp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.into_iter()
+    }
+}
+
+/// Run `apply` with writes recorded instead of made.
+pub(crate) fn record(apply: impl FnOnce() -> Result<()>) -> Result<ProxyPlan> {
+    let outer = RECORDER.with(|recorder| recorder.replace(Some(Vec::new())));
+    let result = apply();
+    let ops = RECORDER.with(|recorder| recorder.replace(outer));
+    result?;
+    Ok(ProxyPlan {
+        ops: ops.unwrap_or_default(),
+    })
+}
+
+/// Whether a dry run is in progress.
+#[inline]
+pub(crate) fn recording() -> bool {
+    RECORDER.with(|recorder| recorder.borrow().is_some())
+}
+
+/// Record `op` if a dry run is in progress. Returns `false` when the write has to be made.
+#[inline]
+pub(crate) fn planned(op: impl FnOnce() -> ProxyOp) -> bool {
+    RECORDER.with(|recorder| match recorder.borrow_mut().as_mut() {
+        Some(ops) => {
+            ops.push(op());
+            true
+        }
+        None => false,
+    })
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+impl Sysproxy {
+    /// The writes [`Sysproxy::set_system_proxy`] would make, without making them.
+    ///
+    /// Example:
+    /// ```no_run
+    /// use sysproxy::Sysproxy;
+    ///
+    /// let proxy = Sysproxy::builder().host("127.0.0.1").port(7890).build().unwrap();
+    /// print!("{}", proxy.plan().unwrap());
+    /// ```
+    #[inline]
+    pub fn plan(&self) -> Result<ProxyPlan> {
+        record(|| self.set_system_proxy())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+impl Autoproxy {
+    /// The writes [`Autoproxy::set_auto_proxy`] would make, without making them.
+    #[inline]
+    pub fn plan(&self) -> Result<ProxyPlan> {
+        record(|| self.set_auto_proxy())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+impl ProxyMode {
+    /// The writes [`ProxyMode::set_proxy_mode`] would make, without making them.
+    #[inline]
+    pub fn plan(&self) -> Result<ProxyPlan> {
+        record(|| self.set_proxy_mode())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn record_captures_instead_of_running() {
+        let plan = record(|| {
+            let mut command = Command::new("gsettings");
+            command.args(["set", "org.gnome.system.proxy", "mode", "'manual'"]);
+            assert!(planned(|| ProxyOp::from(&command)));
+            assert!(planned(|| {
+                ProxyOp::new("dconf", ["write", "/system/proxy/mode", "'manual'"])
+            }));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(plan.ops().len(), 2);
+        assert_eq!(
+            plan.to_string(),
+            "gsettings set org.gnome.system.proxy mode ''\\''manual'\\'''\n\
+             dconf write /system/proxy/mode ''\\''manual'\\'''\n"
+        );
+        assert!(!planned(|| ProxyOp::new("never", [""; 0])));
+    }
+
+    #[test]
+    fn recorded_passwords_are_redacted() {
+        let plan = record(|| {
+            let mut command = Command::new("gsettings");
+            command.args(["set", "org.gnome.system.proxy.http"]);
+            command.args(["authentication-password", "'hunter2'"]);
+            assert!(planned(|| ProxyOp::from(&command)));
+            assert!(planned(|| {
+                ProxyOp::new(
+                    "write",
+                    ["/etc/dnf/dnf.conf", "[main]\nproxy_password=hunter2\n"],
+                )
+            }));
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(!plan.to_string().contains("hunter2"));
+        assert!(!format!("{plan:?}").contains("hunter2"));
+        assert_eq!(plan.ops()[0].args[3], "<redacted>");
+        assert_eq!(plan.ops()[0].raw_args()[3], "'hunter2'");
+    }
+
+    #[test]
+    fn record_passes_errors_through() {
+        assert!(matches!(
+            record(|| Err(Error::NotSupport)),
+            Err(Error::NotSupport)
+        ));
+        assert!(!planned(|| ProxyOp::new("never", [""; 0])));
+    }
+
+    #[test]
+    fn shell_quote_only_quotes_when_needed() {
+        assert_eq!(shell_quote("127.0.0.1"), "127.0.0.1");
+        assert_eq!(shell_quote("Proxy Settings"), "'Proxy Settings'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn plan_serializes_as_list() {
+        let plan = record(|| {
+            planned(|| ProxyOp::new("dconf", ["write", "/system/proxy/mode", "'none'"]));
+            Ok(())
+        })
+        .unwrap();
+        let json = serde_json::to_string(&plan).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"program":"dconf","args":["write","/system/proxy/mode","'none'"]}]"#
+        );
+        assert_eq!(serde_json::from_str::<ProxyPlan>(&json).unwrap(), plan);
+    }
+}
@@ -0,0 +1,192 @@
+//! Flatpak and Snap detection, and how the Linux backend reaches the host session from them.
+//!
+//! Inside a Flatpak, `gsettings` writes the sandbox's own keyfile and kioslaverc lives under
+//! `~/.var/app`, so the host proxy never changes. Apps allowed to talk to
+//! `org.freedesktop.Flatpak` run the commands through `flatpak-spawn --host` instead. A snap
+//! reaches the user's dconf database through its `gsettings` interface, but never the host
+//! kioslaverc. Everything else returns [`Error::Sandboxed`].
+
+use crate::{Error, Result, kconfig::KConfig, utils::CommandExt};
+use std::{env, path::Path, process::Command, sync::LazyLock};
+
+const FLATPAK_INFO: &str = "/.flatpak-info";
+
+static IS_APPIMAGE: LazyLock<bool> = LazyLock::new(|| env::var("APPIMAGE").is_ok());
+
+static CURRENT: LazyLock<Option<Sandbox>> =
+    LazyLock::new(|| Sandbox::detect_from(|name| env::var(name).ok(), Path::exists));
+
+/// The Flatpak may run commands on the host.
+static FLATPAK_HOST: LazyLock<bool> = LazyLock::new(|| {
+    CURRENT.is_some_and(|sandbox| sandbox == Sandbox::Flatpak)
+        && KConfig::load(Path::new(FLATPAK_INFO)).is_ok_and(|info| flatpak_host_access(&info))
+});
+
+/// The snap's `gsettings` interface is connected.
+static SNAP_GSETTINGS: LazyLock<bool> = LazyLock::new(|| {
+    match Command::new("snapctl")
+        .args(["is-connected", "gsettings"])
+        .run()
+    {
+        Ok(_) => true,
+        Err(Error::CommandFailed { .. }) => false,
+        // snapd older than 2.43 can't tell, so let gsettings try.
+        Err(e) => {
+            log::debug!("Failed to check the gsettings interface: {e}");
+            true
+        }
+    }
+});
+
+/// An app sandbox the process runs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Sandbox {
+    Flatpak,
+    Snap,
+}
+
+impl Sandbox {
+    #[inline]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Sandbox::Flatpak => "flatpak",
+            Sandbox::Snap => "snap",
+        }
+    }
+
+    /// The sandbox of this process, detected once. `None` outside of one.
+    #[inline]
+    pub fn current() -> Option<Sandbox> {
+        *CURRENT
+    }
+
+    /// Flatpak from `/.flatpak-info` or `FLATPAK_ID`, Snap from `SNAP`.
+    fn detect_from(
+        var: impl Fn(&str) -> Option<String>,
+        exists: impl Fn(&Path) -> bool,
+    ) -> Option<Sandbox> {
+        if exists(Path::new(FLATPAK_INFO)) || var("FLATPAK_ID").is_some() {
+            return Some(Sandbox::Flatpak);
+        }
+        var("SNAP").map(|_| Sandbox::Snap)
+    }
+
+    /// [`Error::Sandboxed`] for this sandbox, with `guidance` for the user.
+    fn error(self, guidance: String) -> Error {
+        Error::Sandboxed {
+            sandbox: self,
+            guidance,
+        }
+    }
+}
+
+/// Full session bus access, or a `talk` policy for `org.freedesktop.Flatpak`, which is what
+/// `flatpak-spawn --host` calls.
+fn flatpak_host_access(info: &KConfig) -> bool {
+    let full_bus = info
+        .get("Context", "sockets")
+        .is_some_and(|sockets| sockets.split(';').any(|socket| socket == "session-bus"));
+    let policy = info.get("Session Bus Policy", "org.freedesktop.Flatpak");
+    full_bus || matches!(policy.as_deref(), Some("talk" | "own"))
+}
+
+/// Commands run on the host through `flatpak-spawn --host`.
+#[inline]
+pub(crate) fn spawns_on_host() -> bool {
+    *FLATPAK_HOST
+}
+
+/// `program` run in the host session: through `flatpak-spawn --host` from a Flatpak that may,
+/// directly otherwise.
+pub(crate) fn host_command(program: &str) -> Command {
+    if !spawns_on_host() {
+        return local_command(program);
+    }
+    let mut command = Command::new("flatpak-spawn");
+    command.args(["--host", program]);
+    command
+}
+
+/// `program` run from this process, without the libraries an AppImage bundles.
+pub(crate) fn local_command(program: &str) -> Command {
+    let mut command = Command::new(program);
+    if *IS_APPIMAGE {
+        command.env_remove("LD_LIBRARY_PATH");
+    }
+    command
+}
+
+/// Fail with [`Error::Sandboxed`] when gsettings can't reach the user's settings.
+pub(crate) fn check_gsettings() -> Result<()> {
+    match Sandbox::current() {
+        Some(Sandbox::Flatpak) if !spawns_on_host() => Err(flatpak_error()),
+        Some(Sandbox::Snap) if !*SNAP_GSETTINGS => {
+            let name = env::var("SNAP_NAME").unwrap_or_else(|_| "<snap>".into());
+            Err(Sandbox::Snap.error(format!(
+                "connect its gsettings interface with `snap connect {name}:gsettings`"
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Fail with [`Error::Sandboxed`] when the host kioslaverc is out of reach.
+pub(crate) fn check_kioslaverc() -> Result<()> {
+    match Sandbox::current() {
+        Some(Sandbox::Flatpak) if !spawns_on_host() => Err(flatpak_error()),
+        Some(Sandbox::Snap) => Err(Sandbox::Snap.error(
+            "snaps can't change KDE's proxy, set it in System Settings or run the app \
+             outside the snap"
+                .into(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn flatpak_error() -> Error {
+    let id = env::var("FLATPAK_ID").unwrap_or_else(|_| "<app-id>".into());
+    Sandbox::Flatpak.error(format!(
+        "allow it to run host commands with \
+         `flatpak override --user --talk-name=org.freedesktop.Flatpak {id}`"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn detects_from_env_and_info_file() {
+        let detect = |vars: &[(&str, &str)], info: bool| {
+            let vars: HashMap<_, _> = vars.iter().copied().collect();
+            Sandbox::detect_from(|name| vars.get(name).map(|v| v.to_string()), |_| info)
+        };
+        assert_eq!(detect(&[], true), Some(Sandbox::Flatpak));
+        assert_eq!(
+            detect(&[("FLATPAK_ID", "org.example.App")], false),
+            Some(Sandbox::Flatpak)
+        );
+        assert_eq!(
+            detect(&[("SNAP", "/snap/app/1")], false),
+            Some(Sandbox::Snap)
+        );
+        assert_eq!(detect(&[("APPIMAGE", "/tmp/app")], false), None);
+    }
+
+    #[test]
+    fn flatpak_host_access_needs_the_flatpak_name() {
+        let access = |info: &str| flatpak_host_access(&KConfig::parse(info));
+        assert!(!access("[Application]\nname=org.example.App\n"));
+        assert!(!access(
+            "[Context]\nsockets=x11;wayland;\n\n[Session Bus Policy]\norg.freedesktop.Notifications=talk\n"
+        ));
+        assert!(access(
+            "[Session Bus Policy]\norg.freedesktop.Flatpak=talk\n"
+        ));
+        assert!(access("[Context]\nsockets=wayland;session-bus;\n"));
+    }
+}
@@ -1,14 +1,20 @@
-use crate::{Autoproxy, Result, Sysproxy};
+use crate::plan::{ProxyOp, planned};
+use crate::{
+    Autoproxy, BypassList, BypassRule, Error, ProxyBackend, ProxyChange, ProxyDiff, ProxyEndpoint,
+    ProxyEndpoints, ProxyMode, ProxyScheme, Result, SnapshotEntry, Sysproxy,
+    utils::{format_host_port, ipv4_cidr_to_wildcard, parse_host_port},
+};
 use std::{ffi::c_void, mem::size_of};
-use url::Url;
 use windows::{
     Win32::{
+        Foundation::{GlobalFree, HGLOBAL},
         NetworkManagement::Rras::{ERROR_BUFFER_TOO_SMALL, RASENTRYNAMEW, RasEnumEntriesW},
         Networking::WinInet::{
             INTERNET_OPTION_PER_CONNECTION_OPTION, INTERNET_OPTION_PROXY_SETTINGS_CHANGED,
-            INTERNET_OPTION_REFRESH, INTERNET_PER_CONN_AUTOCONFIG_URL, INTERNET_PER_CONN_FLAGS,
-            INTERNET_PER_CONN_OPTION_LISTW, INTERNET_PER_CONN_OPTIONW, INTERNET_PER_CONN_OPTIONW_0,
-            INTERNET_PER_CONN_PROXY_BYPASS, INTERNET_PER_CONN_PROXY_SERVER, InternetSetOptionW,
+            INTERNET_OPTION_REFRESH, INTERNET_PER_CONN, INTERNET_PER_CONN_AUTOCONFIG_URL,
+            INTERNET_PER_CONN_FLAGS, INTERNET_PER_CONN_OPTION_LISTW, INTERNET_PER_CONN_OPTIONW,
+            INTERNET_PER_CONN_OPTIONW_0, INTERNET_PER_CONN_PROXY_BYPASS,
+            INTERNET_PER_CONN_PROXY_SERVER, InternetQueryOptionW, InternetSetOptionW,
             PROXY_TYPE_AUTO_DETECT, PROXY_TYPE_AUTO_PROXY_URL, PROXY_TYPE_DIRECT, PROXY_TYPE_PROXY,
         },
     },
@@ -26,17 +32,71 @@ fn encode_wide<S: AsRef<std::ffi::OsStr>>(string: S) -> Vec<u16> {
         .collect::<Vec<u16>>()
 }
 
-/// unset proxy
+/// 读取局域网 LAN 的 INTERNET_PER_CONN_FLAGS
+#[inline]
+fn query_flags() -> Result<u32> {
+    let mut option = INTERNET_PER_CONN_OPTIONW {
+        dwOption: INTERNET_PER_CONN_FLAGS,
+        Value: INTERNET_PER_CONN_OPTIONW_0::default(),
+    };
+    let mut opts = INTERNET_PER_CONN_OPTION_LISTW {
+        dwSize: size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32,
+        dwOptionCount: 1,
+        dwOptionError: 0,
+        pOptions: &mut option,
+        pszConnection: PWSTR::null(),
+    };
+    let mut size = size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32;
+    unsafe {
+        InternetQueryOptionW(
+            None,
+            INTERNET_OPTION_PER_CONNECTION_OPTION,
+            Some(&mut opts as *mut INTERNET_PER_CONN_OPTION_LISTW as *mut c_void),
+            &mut size,
+        )?;
+        Ok(option.Value.dwValue)
+    }
+}
+
+/// 清除指定的代理类型，保留其余类型
+#[inline]
+const fn without_flags(flags: u32, mask: u32) -> u32 {
+    (flags & !mask) | PROXY_TYPE_DIRECT
+}
+
+/// 按优先级解析代理模式：PAC > 自动检测 > 手动代理
+#[inline]
+fn mode_from_flags(
+    flags: u32,
+    auto_url: impl FnOnce() -> Result<String>,
+    manual: impl FnOnce() -> Result<Sysproxy>,
+) -> Result<ProxyMode> {
+    if flags & PROXY_TYPE_AUTO_PROXY_URL != 0 {
+        let url = auto_url()?;
+        if !url.is_empty() {
+            return Ok(ProxyMode::Auto(url));
+        }
+    }
+    if flags & PROXY_TYPE_AUTO_DETECT != 0 {
+        return Ok(ProxyMode::AutoDetect);
+    }
+    if flags & PROXY_TYPE_PROXY != 0 {
+        return Ok(ProxyMode::Manual(Box::new(manual()?)));
+    }
+    Ok(ProxyMode::Direct)
+}
+
+/// set proxy flags only
 ///
 /// **对于包含中文字符的拨号连接或 VPN 连接，可能无法正确设置其代理，建议使用全英文重命名该连接名称**
 #[inline]
-fn unset_proxy() -> Result<()> {
+fn set_proxy_flags(flags: u32) -> Result<()> {
     let mut p_opts = Vec::<INTERNET_PER_CONN_OPTIONW>::with_capacity(1);
     p_opts.push(INTERNET_PER_CONN_OPTIONW {
         dwOption: INTERNET_PER_CONN_FLAGS,
         Value: {
             let mut v = INTERNET_PER_CONN_OPTIONW_0::default();
-            v.dwValue = PROXY_TYPE_DIRECT;
+            v.dwValue = flags;
             v
         },
     });
@@ -56,7 +116,7 @@ fn unset_proxy() -> Result<()> {
         let conn_wide = encode_wide(ras_conn);
         opts.pszConnection = PWSTR::from_raw(conn_wide.as_ptr() as *mut u16);
         apply_option(&opts)?;
-        log::debug!("unset RAS[{ras_conn}] proxy success");
+        log::debug!("set RAS[{ras_conn}] proxy flags {flags:#x} success");
     }
     notify_proxy_change()
 }
@@ -149,8 +209,37 @@ fn set_global_proxy(server: &str, bypass: &str) -> Result<()> {
     notify_proxy_change()
 }
 
+/// 将选项列表描述为一个操作，用于 dry run
+///
+/// # Safety
+/// `options` 的 pOptions 与 pszConnection 必须有效
+unsafe fn describe_option(options: &INTERNET_PER_CONN_OPTION_LISTW) -> ProxyOp {
+    let connection = match options.pszConnection.is_null() {
+        true => "LAN".to_string(),
+        false => unsafe { options.pszConnection.to_string() }.unwrap_or_default(),
+    };
+    let mut args = vec![format!("connection={connection}")];
+    let p_opts =
+        unsafe { std::slice::from_raw_parts(options.pOptions, options.dwOptionCount as usize) };
+    for opt in p_opts {
+        let name = SNAPSHOT_OPTIONS
+            .iter()
+            .find(|(option, _)| *option == opt.dwOption)
+            .map_or("unknown", |(_, name)| *name);
+        let value = match opt.dwOption == INTERNET_PER_CONN_FLAGS {
+            true => format!("{:#x}", unsafe { opt.Value.dwValue }),
+            false => unsafe { opt.Value.pszValue.to_string() }.unwrap_or_default(),
+        };
+        args.push(format!("{name}={value}"));
+    }
+    ProxyOp::new("InternetSetOptionW", args)
+}
+
 #[inline]
 fn apply_option(options: &INTERNET_PER_CONN_OPTION_LISTW) -> Result<()> {
+    if planned(|| unsafe { describe_option(options) }) {
+        return Ok(());
+    }
     unsafe {
         // setting options
         let opts = options as *const INTERNET_PER_CONN_OPTION_LISTW as *const c_void;
@@ -166,6 +255,17 @@ fn apply_option(options: &INTERNET_PER_CONN_OPTION_LISTW) -> Result<()> {
 
 #[inline]
 fn notify_proxy_change() -> Result<()> {
+    if planned(|| {
+        ProxyOp::new(
+            "InternetSetOptionW",
+            [
+                "INTERNET_OPTION_PROXY_SETTINGS_CHANGED",
+                "INTERNET_OPTION_REFRESH",
+            ],
+        )
+    }) {
+        return Ok(());
+    }
     unsafe {
         InternetSetOptionW(None, INTERNET_OPTION_PROXY_SETTINGS_CHANGED, None, 0)?;
         // refreshing
@@ -174,6 +274,166 @@ fn notify_proxy_change() -> Result<()> {
     Ok(())
 }
 
+/// 快照保存的每个连接选项，FLAGS 的值为十进制
+const SNAPSHOT_OPTIONS: [(INTERNET_PER_CONN, &str); 4] = [
+    (INTERNET_PER_CONN_FLAGS, "flags"),
+    (INTERNET_PER_CONN_PROXY_SERVER, "proxy-server"),
+    (INTERNET_PER_CONN_PROXY_BYPASS, "proxy-bypass"),
+    (INTERNET_PER_CONN_AUTOCONFIG_URL, "autoconfig-url"),
+];
+
+/// 读取系统分配的字符串并释放其内存
+///
+/// # Safety
+/// `value` 必须为空，或由 InternetQueryOptionW 返回
+#[inline]
+unsafe fn take_wide(value: PWSTR) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+    let s = unsafe { value.to_string() }.ok();
+    let _ = unsafe { GlobalFree(HGLOBAL(value.as_ptr() as *mut c_void)) };
+    s
+}
+
+/// 读取一个连接（空字符串为局域网 LAN）的全部快照选项
+fn query_connection(connection: &str) -> Result<Vec<SnapshotEntry>> {
+    let conn_wide = encode_wide(connection);
+    let mut p_opts = SNAPSHOT_OPTIONS
+        .iter()
+        .map(|(option, _)| INTERNET_PER_CONN_OPTIONW {
+            dwOption: *option,
+            Value: INTERNET_PER_CONN_OPTIONW_0::default(),
+        })
+        .collect::<Vec<_>>();
+    let mut opts = INTERNET_PER_CONN_OPTION_LISTW {
+        dwSize: size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32,
+        dwOptionCount: p_opts.len() as u32,
+        dwOptionError: 0,
+        pOptions: p_opts.as_mut_ptr(),
+        pszConnection: match connection.is_empty() {
+            true => PWSTR::null(),
+            false => PWSTR::from_raw(conn_wide.as_ptr() as *mut u16),
+        },
+    };
+    let mut size = size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32;
+    unsafe {
+        InternetQueryOptionW(
+            None,
+            INTERNET_OPTION_PER_CONNECTION_OPTION,
+            Some(&mut opts as *mut INTERNET_PER_CONN_OPTION_LISTW as *mut c_void),
+            &mut size,
+        )?;
+    }
+
+    let mut entries = Vec::with_capacity(SNAPSHOT_OPTIONS.len());
+    for ((option, key), value) in SNAPSHOT_OPTIONS.iter().zip(p_opts.iter()) {
+        let value = match *option == INTERNET_PER_CONN_FLAGS {
+            true => Some(unsafe { value.Value.dwValue }.to_string()),
+            false => unsafe { take_wide(value.Value.pszValue) },
+        };
+        entries.push(SnapshotEntry::new(connection, *key, value));
+    }
+    Ok(entries)
+}
+
+/// 局域网 LAN 及所有拨号连接/VPN 的代理选项
+pub(crate) fn capture_snapshot() -> Result<Vec<SnapshotEntry>> {
+    let mut entries = query_connection("")?;
+    for ras_conn in get_ras_connections()? {
+        entries.extend(query_connection(&ras_conn)?);
+    }
+    Ok(entries)
+}
+
+/// 按连接写回快照选项，快照之后新建的连接保持不变
+pub(crate) fn restore_snapshot(entries: &[SnapshotEntry]) -> Result<()> {
+    let mut connections = Vec::<&str>::new();
+    for entry in entries {
+        if !connections.contains(&entry.scope.as_str()) {
+            connections.push(&entry.scope);
+        }
+    }
+
+    for connection in connections {
+        let value_of = |key: &str| {
+            entries
+                .iter()
+                .find(|entry| entry.scope == connection && entry.key == key)
+                .and_then(|entry| entry.value.as_deref())
+        };
+        let flags = match value_of("flags") {
+            Some(flags) => flags
+                .parse::<u32>()
+                .map_err(|_| Error::ParseStr(flags.into()))?,
+            None => PROXY_TYPE_DIRECT,
+        };
+        // 保持宽字符串在 apply_option 期间存活
+        let strings = SNAPSHOT_OPTIONS[1..]
+            .iter()
+            .map(|(_, key)| encode_wide(value_of(key).unwrap_or_default()))
+            .collect::<Vec<_>>();
+
+        let mut p_opts = Vec::<INTERNET_PER_CONN_OPTIONW>::with_capacity(SNAPSHOT_OPTIONS.len());
+        p_opts.push(INTERNET_PER_CONN_OPTIONW {
+            dwOption: INTERNET_PER_CONN_FLAGS,
+            Value: INTERNET_PER_CONN_OPTIONW_0 { dwValue: flags },
+        });
+        for ((option, _), s) in SNAPSHOT_OPTIONS[1..].iter().zip(strings.iter()) {
+            p_opts.push(INTERNET_PER_CONN_OPTIONW {
+                dwOption: *option,
+                Value: INTERNET_PER_CONN_OPTIONW_0 {
+                    pszValue: PWSTR::from_raw(s.as_ptr() as *mut u16),
+                },
+            });
+        }
+
+        let conn_wide = encode_wide(connection);
+        let opts = INTERNET_PER_CONN_OPTION_LISTW {
+            dwSize: size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32,
+            dwOptionCount: p_opts.len() as u32,
+            dwOptionError: 0,
+            pOptions: p_opts.as_mut_ptr(),
+            pszConnection: match connection.is_empty() {
+                true => PWSTR::null(),
+                false => PWSTR::from_raw(conn_wide.as_ptr() as *mut u16),
+            },
+        };
+        apply_option(&opts)?;
+        log::debug!("restore [{connection}] proxy options success");
+    }
+    notify_proxy_change()
+}
+
+/// Backend for the WinINet per-connection settings. The inherent methods use the same settings.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WindowsBackend;
+
+impl ProxyBackend for WindowsBackend {
+    fn get_system_proxy(&self) -> Result<Sysproxy> {
+        Sysproxy::get_system_proxy()
+    }
+
+    fn set_system_proxy(&self, proxy: &Sysproxy) -> Result<()> {
+        proxy.set_system_proxy()
+    }
+
+    /// Credentials are never read back, so they are left out.
+    fn system_proxy_diff(&self, current: &Sysproxy, proxy: &Sysproxy) -> ProxyDiff {
+        current
+            .diff(proxy)
+            .retain(|change| *change != ProxyChange::Auth)
+    }
+
+    fn get_auto_proxy(&self) -> Result<Autoproxy> {
+        Autoproxy::get_auto_proxy()
+    }
+
+    fn set_auto_proxy(&self, autoproxy: &Autoproxy) -> Result<()> {
+        autoproxy.set_auto_proxy()
+    }
+}
+
 impl Sysproxy {
     #[inline]
     pub fn get_system_proxy() -> Result<Sysproxy> {
@@ -184,47 +444,48 @@ impl Sysproxy {
             .get_value::<String, _>("ProxyServer")
             .unwrap_or_default();
 
-        // 预设默认值
-        let mut host = String::new();
-        let mut port = 0u16;
-
-        if !proxy_server.is_empty() {
-            if proxy_server.contains('=') {
-                // 处理多协议格式: http=127.0.0.1:7890;https=127.0.0.1:7890
-                // 优先查找http代理
-                let http_proxy = proxy_server
-                    .split(';')
-                    .find(|part| {
-                        let t = part.trim().as_bytes();
-                        t.len() >= 5 && t[..5].eq_ignore_ascii_case(b"http=")
-                    })
-                    .or_else(|| proxy_server.split(';').next());
-
-                if let Some(proxy) = http_proxy {
-                    let proxy_value = proxy.split('=').nth(1).unwrap_or("");
-                    parse_proxy_address(proxy_value, &mut host, &mut port);
-                }
-            } else {
-                // 处理单一格式: 127.0.0.1:7890
-                parse_proxy_address(&proxy_server, &mut host, &mut port);
-            }
+        let bypass = cur_var
+            .get_value::<String, _>("ProxyOverride")
+            .unwrap_or_default();
+        let bypass = BypassList::parse_lossy(&bypass);
+
+        if proxy_server.contains('=') {
+            // 处理多协议格式: http=127.0.0.1:7890;https=127.0.0.1:7890;socks=127.0.0.1:7891
+            return Ok(Sysproxy::from_endpoints(
+                enable,
+                parse_proxy_server(&proxy_server),
+                bypass,
+            ));
         }
 
-        let bypass = cur_var.get_value("ProxyOverride").unwrap_or_default();
+        // 处理单一格式: 127.0.0.1:7890
+        let (host, port) = if proxy_server.is_empty() {
+            (String::new(), 0u16)
+        } else {
+            parse_host_port(&proxy_server)
+        };
 
         Ok(Sysproxy {
             enable,
             host,
             port,
             bypass,
+            endpoints: ProxyEndpoints::default(),
+            auth: None,
         })
     }
 
     #[inline]
     pub fn set_system_proxy(&self) -> Result<()> {
+        self.validate()?;
+
         match self.enable {
-            true => set_global_proxy(&format!("{}:{}", self.host, self.port), &self.bypass),
-            false => unset_proxy(),
+            true => set_global_proxy(
+                &format_proxy_server(self),
+                &format_proxy_override(&self.bypass),
+            ),
+            // 只关闭手动代理，保留 PAC 与自动检测
+            false => set_proxy_flags(without_flags(query_flags()?, PROXY_TYPE_PROXY)),
         }
     }
 }
@@ -245,39 +506,102 @@ impl Autoproxy {
     pub fn set_auto_proxy(&self) -> Result<()> {
         match self.enable {
             true => set_auto_proxy(&self.url),
-            false => unset_proxy(),
+            // 只关闭 PAC，保留手动代理
+            false => set_proxy_flags(without_flags(
+                query_flags()?,
+                PROXY_TYPE_AUTO_PROXY_URL | PROXY_TYPE_AUTO_DETECT,
+            )),
         }
     }
 }
 
-/// 解析代理地址字符串为主机名和端口
+impl ProxyMode {
+    #[inline]
+    pub fn get_proxy_mode() -> Result<ProxyMode> {
+        mode_from_flags(
+            query_flags()?,
+            || Autoproxy::get_auto_proxy().map(|auto| auto.url),
+            || {
+                Sysproxy::get_system_proxy().map(|proxy| Sysproxy {
+                    enable: true,
+                    ..proxy
+                })
+            },
+        )
+    }
+
+    #[inline]
+    pub fn set_proxy_mode(&self) -> Result<()> {
+        match self {
+            ProxyMode::Direct => set_proxy_flags(PROXY_TYPE_DIRECT),
+            ProxyMode::Manual(proxy) => Sysproxy {
+                enable: true,
+                ..(**proxy).clone()
+            }
+            .set_system_proxy(),
+            ProxyMode::Auto(url) => set_auto_proxy(url),
+            ProxyMode::AutoDetect => set_proxy_flags(PROXY_TYPE_AUTO_DETECT | PROXY_TYPE_DIRECT),
+        }
+    }
+}
+
+/// 解析多协议格式的 ProxyServer，未知协议会被忽略
 #[inline]
-fn parse_proxy_address(address: &str, host: &mut String, port: &mut u16) {
-    // 快速路径：host:port 或 [ipv6]:port，无需堆分配
-    if let Some((h, p)) = address.rsplit_once(':') {
-        if let Ok(port_num) = p.parse::<u16>() {
-            // 去除 IPv6 方括号: "[::1]" → "::1"
-            let clean = if h.starts_with('[') && h.ends_with(']') {
-                &h[1..h.len() - 1]
-            } else {
-                h
-            };
-            *host = clean.to_string();
-            *port = port_num;
-            return;
+fn parse_proxy_server(proxy_server: &str) -> ProxyEndpoints {
+    let mut endpoints = ProxyEndpoints::default();
+    for part in proxy_server.split(';') {
+        let Some((scheme, address)) = part.trim().split_once('=') else {
+            continue;
+        };
+        let scheme = match scheme.trim().to_ascii_lowercase().as_str() {
+            "http" => ProxyScheme::Http,
+            "https" => ProxyScheme::Https,
+            "socks" => ProxyScheme::Socks,
+            "ftp" => ProxyScheme::Ftp,
+            _ => continue,
+        };
+        let (host, port) = parse_host_port(address.trim());
+        if !host.is_empty() {
+            endpoints.set(scheme, Some(ProxyEndpoint::new(host, port)));
         }
     }
+    endpoints
+}
 
-    // 回退：URL 解析器处理无端口的主机名等边缘情况
-    if let Ok(url) = Url::parse(&format!("http://{}", address)) {
-        *host = url.host_str().unwrap_or("").to_string();
-        *port = url.port().unwrap_or(80);
-        return;
+/// 生成 ProxyServer，未单独设置协议时使用单一格式
+#[inline]
+fn format_proxy_server(proxy: &Sysproxy) -> String {
+    if proxy.endpoints.is_empty() {
+        return format_host_port(&proxy.host, proxy.port);
     }
+    ProxyScheme::ALL
+        .iter()
+        .filter_map(|scheme| {
+            proxy
+                .endpoint(*scheme)
+                .map(|e| format!("{}={}", scheme.as_str(), format_host_port(&e.host, e.port)))
+        })
+        .collect::<Vec<String>>()
+        .join(";")
+}
 
-    // 如果无法解析端口，默认使用主机名和标准HTTP端口
-    *host = address.to_string();
-    *port = 80;
+/// 生成 ProxyOverride，WinINet 不支持 CIDR，IPv4 网段展开为通配符，IPv6 网段被忽略
+#[inline]
+fn format_proxy_override(bypass: &BypassList) -> String {
+    let mut entries = Vec::with_capacity(bypass.len());
+    for rule in bypass {
+        match rule {
+            BypassRule::Ipv4Cidr(addr, prefix) => {
+                match ipv4_cidr_to_wildcard(&format!("{addr}/{prefix}")) {
+                    Ok(wildcards) => entries.extend(wildcards),
+                    Err(e) => log::debug!("skip bypass entry {rule}: {e}"),
+                }
+            }
+            BypassRule::Ipv6Cidr(..) => log::debug!("skip unsupported bypass entry {rule}"),
+            _ => entries.push(rule.to_string()),
+        }
+    }
+    entries.join(";")
 }
 
 /// refer: https://learn.microsoft.com/zh-cn/windows/win32/api/ras/nf-ras-rasenumentriesw
@@ -355,52 +679,81 @@ fn get_ras_connections() -> Result<Vec<String>> {
 
 #[cfg(test)]
 mod tests {
-    use super::parse_proxy_address;
-
-    fn parse(addr: &str) -> (String, u16) {
-        let mut host = String::new();
-        let mut port = 0u16;
-        parse_proxy_address(addr, &mut host, &mut port);
-        (host, port)
-    }
-
-    #[test]
-    fn test_ipv4_with_port() {
-        assert_eq!(parse("127.0.0.1:8080"), ("127.0.0.1".into(), 8080));
-    }
+    #![allow(clippy::unwrap_used)]
+    use super::{
+        PROXY_TYPE_AUTO_DETECT, PROXY_TYPE_AUTO_PROXY_URL, PROXY_TYPE_DIRECT, PROXY_TYPE_PROXY,
+        format_proxy_override, format_proxy_server, mode_from_flags, parse_proxy_server,
+        without_flags,
+    };
+    use crate::{BypassList, ProxyEndpoint, ProxyEndpoints, ProxyMode, Sysproxy};
 
     #[test]
-    fn test_hostname_with_port() {
-        assert_eq!(
-            parse("proxy.example.com:3128"),
-            ("proxy.example.com".into(), 3128)
-        );
+    fn test_multi_protocol_proxy_server() {
+        let endpoints =
+            parse_proxy_server("http=127.0.0.1:7890;https=127.0.0.1:7890;socks=127.0.0.1:7891");
+        assert_eq!(endpoints.http, Some(ProxyEndpoint::new("127.0.0.1", 7890)));
+        assert_eq!(endpoints.https, Some(ProxyEndpoint::new("127.0.0.1", 7890)));
+        assert_eq!(endpoints.socks, Some(ProxyEndpoint::new("127.0.0.1", 7891)));
+        assert_eq!(endpoints.ftp, None);
     }
 
     #[test]
-    fn test_ipv6_bracketed_with_port() {
-        assert_eq!(parse("[::1]:1080"), ("::1".into(), 1080));
+    fn test_format_proxy_server_round_trip() {
+        let proxy = Sysproxy {
+            enable: true,
+            endpoints: ProxyEndpoints {
+                http: Some(ProxyEndpoint::new("127.0.0.1", 7890)),
+                socks: Some(ProxyEndpoint::new("127.0.0.1", 7891)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let server = format_proxy_server(&proxy);
+        assert_eq!(server, "http=127.0.0.1:7890;socks=127.0.0.1:7891");
+        assert_eq!(parse_proxy_server(&server), proxy.endpoints);
+
+        let single = Sysproxy {
+            host: "::1".into(),
+            port: 7890,
+            ..Default::default()
+        };
+        assert_eq!(format_proxy_server(&single), "[::1]:7890");
     }
 
     #[test]
-    fn test_hostname_only_defaults_port_80() {
-        assert_eq!(parse("proxy.example.com"), ("proxy.example.com".into(), 80));
+    fn test_proxy_override_expands_cidr() {
+        let bypass = BypassList::parse("localhost,127.0.0.1/8,fe80::/10,<local>").unwrap();
+        assert_eq!(format_proxy_override(&bypass), "localhost;127.*;<local>");
     }
 
     #[test]
-    fn test_ipv4_only_defaults_port_80() {
-        assert_eq!(parse("192.168.1.1"), ("192.168.1.1".into(), 80));
+    fn test_disable_keeps_other_flags() {
+        let pac = PROXY_TYPE_AUTO_DETECT | PROXY_TYPE_AUTO_PROXY_URL | PROXY_TYPE_DIRECT;
+        assert_eq!(without_flags(pac, PROXY_TYPE_PROXY), pac);
+        assert_eq!(
+            without_flags(PROXY_TYPE_PROXY | PROXY_TYPE_DIRECT, PROXY_TYPE_PROXY),
+            PROXY_TYPE_DIRECT
+        );
     }
 
     #[test]
-    fn test_empty_string() {
-        let (host, port) = parse("");
-        assert_eq!(port, 80);
-        assert!(host.is_empty());
-    }
+    fn test_mode_from_flags() {
+        let url = || -> crate::Result<String> { Ok("http://127.0.0.1/pac".into()) };
+        let manual = || -> crate::Result<Sysproxy> { Ok(Sysproxy::default()) };
+        let mode = |flags| mode_from_flags(flags, url, manual).unwrap();
 
-    #[test]
-    fn test_high_port() {
-        assert_eq!(parse("10.0.0.1:65535"), ("10.0.0.1".into(), 65535));
+        assert_eq!(mode(PROXY_TYPE_DIRECT), ProxyMode::Direct);
+        assert_eq!(
+            mode(PROXY_TYPE_PROXY | PROXY_TYPE_DIRECT),
+            ProxyMode::Manual(Box::default())
+        );
+        assert_eq!(
+            mode(PROXY_TYPE_AUTO_DETECT | PROXY_TYPE_AUTO_PROXY_URL | PROXY_TYPE_DIRECT),
+            ProxyMode::Auto("http://127.0.0.1/pac".into())
+        );
+        assert_eq!(
+            mode(PROXY_TYPE_AUTO_DETECT | PROXY_TYPE_DIRECT),
+            ProxyMode::AutoDetect
+        );
     }
 }
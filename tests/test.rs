@@ -24,6 +24,20 @@ mod tests {
         Autoproxy::get_auto_proxy().unwrap();
     }
 
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_sys_get_async() {
+        let current = Sysproxy::get_system_proxy_async().await.unwrap();
+        assert_eq!(current, Sysproxy::get_system_proxy().unwrap());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_auto_get_async() {
+        let current = Autoproxy::get_auto_proxy_async().await.unwrap();
+        assert_eq!(current, Autoproxy::get_auto_proxy().unwrap());
+    }
+
     #[test]
     #[serial]
     fn test_system_enable() {
@@ -32,9 +46,10 @@ mod tests {
             host: "127.0.0.1".into(),
             port: 7897,
             #[cfg(target_os = "windows")]
-            bypass: "localhost;127.*".into(),
+            bypass: "localhost;127.*".parse().unwrap(),
             #[cfg(not(target_os = "windows"))]
-            bypass: "localhost,127.0.0.1/8".into(),
+            bypass: "localhost,127.0.0.1/8".parse().unwrap(),
+            ..Default::default()
         };
 
         // Setting proxy requires admin privileges on macOS